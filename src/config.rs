@@ -1,7 +1,8 @@
 use crate::{parser::variables::replace_template_variables, prelude::*};
+use clap::ValueEnum;
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fmt::Display, fs, path::Path};
 use toml::{from_str, to_string};
 
 /// Creates path to config directory specific to OS. A slash is appended to the end of the path
@@ -48,7 +49,7 @@ pub fn write_config<T: SerializeConfig>(config: &T) -> Result<(), Box<dyn Error>
     let config_dir: String = dir_path();
     let config_file: String = file_path();
 
-    let serialized_config: String = to_string(&config.get_whole_config())?;
+    let serialized_config: String = to_string(&config.get_whole_config()?)?;
     trace!("Serialized config");
 
     if !Path::new(&config_dir).exists() {
@@ -104,29 +105,60 @@ fn verify_config_integrity(
 }
 
 pub trait SerializeConfig {
-    fn get_whole_config(&self) -> Config;
+    /// The whole config with this part replaced. Other parts are read from the config file
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>>;
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub credentials: CredentialsConfig,
     pub discord: DiscordConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookConfig>,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub mpd: MpdConfig,
+    #[serde(default)]
+    pub mpris: MprisConfig,
     pub processes: ProcessesConfig,
+    #[serde(default)]
+    pub shell: ShellConfig,
     pub spotify: SpotifyConfig,
+    #[serde(default)]
+    pub task: TaskConfig,
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            credentials: CredentialsConfig::default(),
             discord: DiscordConfig::new(1133837522074607749),
+            hooks: Vec::new(),
+            media: MediaConfig::default(),
+            mpd: MpdConfig::default(),
+            mpris: MprisConfig::default(),
             processes: ProcessesConfig {
+                appear_after: 0,
+                auto: AutoConfig::default(),
                 idle_image: String::from("idle"),
                 idle_text: String::from("Idle"),
+                interval: default_processes_interval(),
+                linger_for: 0,
+                strategy: ProcessStrategy::ConfigOrder,
                 processes: vec![ProcessConfig {
+                    appear_after: None,
                     image: String::from("code"),
+                    linger_for: None,
+                    matcher: ProcessMatcher::Name,
                     name: String::from("code"),
                     text: String::from("Visual Studio Code"),
                 }],
             },
+            shell: ShellConfig::default(),
             spotify: SpotifyConfig {
                 client_id: String::new(),
                 client_secret: String::new(),
@@ -137,18 +169,60 @@ impl Default for Config {
                     name: String::new(),
                     track_url: String::new(),
                 },
+                interval: default_spotify_interval(),
+                redirect_port: None,
+                redirect_uri: default_redirect_uri(),
                 refresh_token: String::new(),
+                scopes: Vec::new(),
+                stale_for: default_spotify_stale_for(),
             },
+            task: TaskConfig::default(),
+            workspace: WorkspaceConfig::default(),
         }
     }
 }
 
 impl SerializeConfig for Config {
-    fn get_whole_config(&self) -> Config {
-        return self.to_owned();
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        return Ok(self.to_owned());
     }
 }
 
+/// Where secrets are kept. They are never written to `ddrpc.toml`, so it can be shared.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CredentialsConfig {
+    /// Backend `auto` last saved the secrets to. Written by ddrpc, so `auto` keeps using it instead of switching to
+    /// another backend that does not have the secrets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_backend: Option<CredentialsBackend>,
+    #[serde(default)]
+    pub backend: CredentialsBackend,
+    /// Encrypt the credentials file with a passphrase, read from `DDRPC_PASSPHRASE` or asked for in a terminal
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+impl SerializeConfig for CredentialsConfig {
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = read_config_file(false)?;
+        config.credentials = self.to_owned();
+        return Ok(config);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialsBackend {
+    /// The Secret Service if one is running and `encrypt` is not set, otherwise the credentials file. Once secrets are
+    /// saved, the same backend is used again
+    #[default]
+    Auto,
+    /// `credentials.toml` next to `ddrpc.toml`, only readable by the user
+    File,
+    /// The desktop keyring through the Secret Service D-Bus API, such as GNOME Keyring or KeePassXC
+    SecretService,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct DiscordConfig {
     pub assets: DiscordConfigAssets,
@@ -159,14 +233,10 @@ pub struct DiscordConfig {
 }
 
 impl SerializeConfig for DiscordConfig {
-    fn get_whole_config(&self) -> Config {
-        return match read_config_file(false) {
-            Err(_) => todo!(),
-            Ok(mut config) => {
-                config.discord = self.to_owned();
-                config
-            }
-        };
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = read_config_file(false)?;
+        config.discord = self.to_owned();
+        return Ok(config);
     }
 }
 
@@ -195,25 +265,25 @@ impl DiscordConfig {
         let config: DiscordConfig = self.to_owned();
 
         self.assets.large_image =
-            replace_template_variables(&template_hashmap, config.assets.large_image);
+            replace_template_variables(template_hashmap, config.assets.large_image);
         self.assets.large_text =
-            replace_template_variables(&template_hashmap, config.assets.large_text);
+            replace_template_variables(template_hashmap, config.assets.large_text);
         self.assets.small_image =
-            replace_template_variables(&template_hashmap, config.assets.small_image);
+            replace_template_variables(template_hashmap, config.assets.small_image);
         self.assets.small_text =
-            replace_template_variables(&template_hashmap, config.assets.small_text);
+            replace_template_variables(template_hashmap, config.assets.small_text);
 
         self.buttons.btn1_text =
-            replace_template_variables(&template_hashmap, config.buttons.btn1_text);
+            replace_template_variables(template_hashmap, config.buttons.btn1_text);
         self.buttons.btn1_url =
-            replace_template_variables(&template_hashmap, config.buttons.btn1_url);
+            replace_template_variables(template_hashmap, config.buttons.btn1_url);
         self.buttons.btn2_text =
-            replace_template_variables(&template_hashmap, config.buttons.btn2_text);
+            replace_template_variables(template_hashmap, config.buttons.btn2_text);
         self.buttons.btn2_url =
-            replace_template_variables(&template_hashmap, config.buttons.btn2_url);
+            replace_template_variables(template_hashmap, config.buttons.btn2_url);
 
-        self.details = replace_template_variables(&template_hashmap, config.details);
-        self.state = replace_template_variables(&template_hashmap, config.state);
+        self.details = replace_template_variables(template_hashmap, config.details);
+        self.state = replace_template_variables(template_hashmap, config.state);
     }
 }
 
@@ -259,26 +329,177 @@ impl DiscordButtons {
     }
 }
 
+/// Command run when an event happens. A hook can have multiple triggers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HookConfig {
+    /// Shell command, run with `sh -c`. Event details are passed in `DDRPC_*` environment variables
+    pub command: String,
+    /// Run when the Discord client connects
+    #[serde(default)]
+    pub discord_connected: bool,
+    /// Run when the rendered Discord activity changes
+    #[serde(default)]
+    pub activity_changed: bool,
+    /// Run when the target process with this name exits. `*` matches any target process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_exited: Option<String>,
+    /// Run when the target process with this name starts. `*` matches any target process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_started: Option<String>,
+    /// Seconds before the command is killed
+    #[serde(default = "default_hook_timeout")]
+    pub timeout: u64,
+    /// Run when the playing track changes: `{{media.source}}`, `{{media.title}}`, `{{media.artists}}` or
+    /// `{{media.album}}`
+    #[serde(default)]
+    pub track_changed: bool,
+}
+
+fn default_hook_timeout() -> u64 {
+    return 10;
+}
+
+/// Sources for the `{{media.*}}` variables.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MediaConfig {
+    /// Sources to ask, first one playing something wins: `spotify_api`, `mpd`, `mpris` for any MPRIS player or
+    /// `mpris:<player>` for a single one. `spotify.fallback` is only used if none of them are playing anything. Only
+    /// `spotify_api` by default, MPD and MPRIS players are read once they are added
+    #[serde(default = "default_media_order")]
+    pub order: Vec<String>,
+    /// Set the activity start and end timestamps from the playing media's position, so Discord shows its progress
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        return Self {
+            order: default_media_order(),
+            timestamps: false,
+        };
+    }
+}
+
+fn default_media_order() -> Vec<String> {
+    return vec![String::from("spotify_api")];
+}
+
+/// Music Player Daemon connection for the `mpd` media source.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MpdConfig {
+    /// `host:port`, or the path of a Unix socket
+    #[serde(default = "default_mpd_address")]
+    pub address: String,
+    /// Kept in the credentials store, only read from here to move it there
+    #[serde(default, skip_serializing)]
+    pub password: String,
+}
+
+impl SerializeConfig for MpdConfig {
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = read_config_file(false)?;
+        config.mpd = self.to_owned();
+        return Ok(config);
+    }
+}
+
+impl Default for MpdConfig {
+    fn default() -> Self {
+        return Self {
+            address: default_mpd_address(),
+            password: String::new(),
+        };
+    }
+}
+
+fn default_mpd_address() -> String {
+    return String::from("localhost:6600");
+}
+
+fn default_player_interval() -> u64 {
+    return 1;
+}
+
+/// Media players read over MPRIS for the `{{media.*}}` variables.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MprisConfig {
+    /// Skips `mpris` entries in `media.order` if disabled
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Players that are never shown, such as `firefox`. Instances like `firefox.instance_1_42` match their player name
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Seconds between reads of the player state
+    #[serde(default = "default_player_interval")]
+    pub interval: u64,
+    /// Player priority, highest first. Playing players are always preferred over paused ones, and players that are
+    /// not listed come after listed ones
+    #[serde(default)]
+    pub players: Vec<String>,
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            ignore: Vec::new(),
+            interval: default_player_interval(),
+            players: Vec::new(),
+        };
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SpotifyConfig {
     pub client_id: String,
+    /// Leave empty to authorize with PKCE, which only needs the client ID. Kept in the credentials store, only read
+    /// from here to move it there
+    #[serde(default, skip_serializing)]
     pub client_secret: String,
     pub fallback: SpotifyFallbackConfig,
+    /// Seconds between requests for the playing track
+    #[serde(default = "default_spotify_interval")]
+    pub interval: u64,
+    /// Port the authorization redirect is caught on, if it differs from the port in `redirect_uri` because it is
+    /// forwarded or proxied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_port: Option<u16>,
+    /// Must be added as a redirect URI in the Spotify app settings
+    #[serde(default = "default_redirect_uri")]
+    pub redirect_uri: String,
+    /// Kept in the credentials store, only read from here to move it there
+    #[serde(default, skip_serializing)]
     pub refresh_token: String,
+    /// Scopes `refresh_token` was granted. Templates that use data needing other scopes cause a new authorization
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// Seconds the last track is still shown for while requests to Spotify fail
+    #[serde(default = "default_spotify_stale_for")]
+    pub stale_for: u64,
 }
 
 impl SerializeConfig for SpotifyConfig {
-    fn get_whole_config(&self) -> Config {
-        return match read_config_file(false) {
-            Err(_) => todo!(),
-            Ok(mut config) => {
-                config.spotify = self.to_owned();
-                config
-            }
-        };
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = read_config_file(false)?;
+        config.spotify = self.to_owned();
+        return Ok(config);
     }
 }
 
+fn default_spotify_interval() -> u64 {
+    return 3;
+}
+
+fn default_spotify_stale_for() -> u64 {
+    return 30;
+}
+
+/// Spotify only allows plain HTTP redirects to loopback addresses
+fn default_redirect_uri() -> String {
+    return String::from("http://127.0.0.1:3000/callback");
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SpotifyFallbackConfig {
     pub album_name: String,
@@ -291,27 +512,261 @@ pub struct SpotifyFallbackConfig {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProcessesConfig {
+    /// Seconds a target process has to be running before it is shown. Can be overridden per process
+    #[serde(default)]
+    pub appear_after: u64,
+    /// Show the most relevant running application when no target process is active
+    #[serde(default)]
+    pub auto: AutoConfig,
     pub idle_image: String,
     pub idle_text: String,
+    /// Seconds between process scans
+    #[serde(default = "default_processes_interval")]
+    pub interval: u64,
+    /// Seconds a target process is still shown for after it exits. Can be overridden per process
+    #[serde(default)]
+    pub linger_for: u64,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub processes: Vec<ProcessConfig>,
+    /// How the shown process is chosen when multiple target processes are active
+    #[serde(default)]
+    pub strategy: ProcessStrategy,
 }
 
 impl SerializeConfig for ProcessesConfig {
-    fn get_whole_config(&self) -> Config {
-        return match read_config_file(false) {
-            Err(_) => todo!(),
-            Ok(mut config) => {
-                config.processes = self.to_owned();
-                config
-            }
+    fn get_whole_config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = read_config_file(false)?;
+        config.processes = self.to_owned();
+        return Ok(config);
+    }
+}
+
+fn default_processes_interval() -> u64 {
+    return 3;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AutoConfig {
+    /// Running applications are only scanned for if the templates use `{{auto.*}}` variables
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Process names that are never chosen, for background daemons that have a `.desktop` file
+    #[serde(default)]
+    pub denylist: Vec<String>,
+    #[serde(default)]
+    pub rank: AutoRank,
+}
+
+impl Default for AutoConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            denylist: [
+                "blueman-applet",
+                "gnome-shell",
+                "ibus-daemon",
+                "kded5",
+                "kded6",
+                "nm-applet",
+                "plasmashell",
+                "xdg-desktop-por",
+            ]
+            .map(String::from)
+            .to_vec(),
+            rank: AutoRank::Cpu,
         };
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoRank {
+    /// Highest CPU usage since the last update
+    #[default]
+    Cpu,
+    /// Most recently started
+    StartTime,
+}
+
+fn default_true() -> bool {
+    return true;
+}
+
+/// `focused` is refused when the config is read, as ddrpc has no window focus provider to choose the process with
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "snake_case", try_from = "String")]
+pub enum ProcessStrategy {
+    /// First active process in config order
+    #[default]
+    ConfigOrder,
+    /// Most recently started process
+    MostRecent,
+    /// Process with the highest CPU usage
+    HighestCpu,
+    /// Process that has been running the longest
+    LongestRunning,
+}
+
+impl TryFrom<String> for ProcessStrategy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        return match value.as_str() {
+            "config_order" => Ok(ProcessStrategy::ConfigOrder),
+            "most_recent" => Ok(ProcessStrategy::MostRecent),
+            "highest_cpu" => Ok(ProcessStrategy::HighestCpu),
+            "longest_running" => Ok(ProcessStrategy::LongestRunning),
+            "focused" => Err(String::from(
+                "the focused strategy needs a window focus provider, which ddrpc does not have. Use config_order, most_recent, highest_cpu or longest_running instead",
+            )),
+            _ => Err(format!(
+                "unknown strategy \"{value}\", expected config_order, most_recent, highest_cpu or longest_running"
+            )),
+        };
+    }
+}
+
+impl Display for ProcessStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(
+            f,
+            "{}",
+            match self {
+                ProcessStrategy::ConfigOrder => "config_order",
+                ProcessStrategy::MostRecent => "most_recent",
+                ProcessStrategy::HighestCpu => "highest_cpu",
+                ProcessStrategy::LongestRunning => "longest_running",
+            }
+        );
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProcessConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appear_after: Option<u64>,
     pub image: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linger_for: Option<u64>,
+    /// What `name` is compared against
+    #[serde(default, skip_serializing_if = "ProcessMatcher::is_name")]
+    pub matcher: ProcessMatcher,
     pub name: String,
     pub text: String,
 }
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessMatcher {
+    /// Exact process name
+    #[default]
+    Name,
+    /// Application ID of a Flatpak, Snap or desktop-launched application, e.g. `org.blender.Blender`
+    AppId,
+}
+
+impl ProcessMatcher {
+    pub fn is_name(&self) -> bool {
+        return *self == ProcessMatcher::Name;
+    }
+}
+
+/// Settings for the `{{shell.*}}` variables reported by the `shell-init` hooks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShellConfig {
+    /// Seconds between reads of the shell state files
+    #[serde(default = "default_shell_interval")]
+    pub interval: u64,
+    /// Regular expressions for secrets in command lines. Matches are replaced with `***`, or only the first capture group
+    /// if the pattern has one
+    #[serde(default = "default_redact")]
+    pub redact: Vec<String>,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        return Self {
+            interval: default_shell_interval(),
+            redact: default_redact(),
+        };
+    }
+}
+
+fn default_shell_interval() -> u64 {
+    return 1;
+}
+
+fn default_redact() -> Vec<String> {
+    return vec![
+        String::from(r"(?i)(?:password|passwd|secret|token|api[_-]?key)[A-Za-z_]*[=: ]\s*(\S+)"),
+        String::from(r#"(?i)authorization:\s*(?:bearer|basic)?\s*([^\s'"]+)"#),
+        String::from(r"://[^/\s:@]+:([^/\s@]+)@"),
+        String::from(r"(?:ghp|gho|github_pat|glpat|xox[abp])_?[A-Za-z0-9_-]{16,}"),
+        // `-p` is only a password for these commands, elsewhere it is usually a path or port
+        String::from(
+            r"\b(?:sshpass|mysql|mysqldump|mysqladmin|mariadb|mariadb-dump)\b.*?\s-p\s*(\S+)",
+        ),
+    ];
+}
+
+/// Activity shown while a command started with `ddrpc run` is running. The details and state replace the ones from
+/// [`DiscordConfig`] unless they are empty, and the activity shows the time elapsed since the command started.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskConfig {
+    #[serde(default = "default_task_details")]
+    pub details: String,
+    /// State shown after the command finished, for `finished_for` seconds
+    #[serde(default = "default_task_finished_state")]
+    pub finished_state: String,
+    /// Seconds to show `finished_state` for. The activity goes back to normal right away if 0
+    #[serde(default)]
+    pub finished_for: u64,
+    #[serde(default)]
+    pub state: String,
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        return Self {
+            details: default_task_details(),
+            finished_state: default_task_finished_state(),
+            finished_for: 0,
+            state: String::new(),
+        };
+    }
+}
+
+fn default_task_details() -> String {
+    return String::from("Running {{task.name}}");
+}
+
+fn default_task_finished_state() -> String {
+    return String::from("Finished in {{task.elapsed}}");
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+    /// Check if tracked files in the repository have been modified
+    #[serde(default = "default_true")]
+    pub check_dirty: bool,
+    /// Seconds between reads of the repository
+    #[serde(default = "default_workspace_interval")]
+    pub interval: u64,
+    /// Directory used to find the repository. If empty, the working directory of the chosen process is used
+    #[serde(default)]
+    pub path: String,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        return Self {
+            check_dirty: true,
+            interval: default_workspace_interval(),
+            path: String::new(),
+        };
+    }
+}
+
+fn default_workspace_interval() -> u64 {
+    return 5;
+}
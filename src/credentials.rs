@@ -0,0 +1,663 @@
+use crate::prelude::*;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{ErrorKind, IsTerminal, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+    sync::OnceLock,
+    time::Duration,
+};
+use tokio::time::timeout;
+use toml::{from_str, to_string, Table};
+use zbus::{
+    connection,
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+/// Environment variable the passphrase of an encrypted credentials file is read from
+pub const PASSPHRASE_VARIABLE: &str = "DDRPC_PASSPHRASE";
+const SALT_LENGTH: usize = 16;
+const KEY_LENGTH: usize = 32;
+
+const SECRETS_SERVICE: &str = "org.freedesktop.secrets";
+const SECRETS_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+/// Attributes the keyring item is found by
+const ITEM_ATTRIBUTES: [(&str, &str); 1] = [("application", "ddrpc")];
+const ITEM_LABEL: &str = "ddrpc credentials";
+/// Starting a keyring through D-Bus activation can take a moment
+const SECRET_SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Passphrase of the credentials file, asked for once per run
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Appends `credentials.toml` to the end of the path produced by `dir_path()`
+fn file_path() -> String {
+    return dir_path() + "credentials.toml";
+}
+
+/// Secrets kept out of `ddrpc.toml`. Has no `Debug` implementation so it cannot end up in logs.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Credentials {
+    #[serde(default, skip_serializing_if = "MpdCredentials::is_empty")]
+    mpd: MpdCredentials,
+    #[serde(default, skip_serializing_if = "SpotifyCredentials::is_empty")]
+    spotify: SpotifyCredentials,
+}
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+struct MpdCredentials {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    password: String,
+}
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+struct SpotifyCredentials {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    client_secret: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    refresh_token: String,
+}
+
+impl Credentials {
+    fn is_empty(&self) -> bool {
+        return *self == Self::default();
+    }
+}
+
+impl MpdCredentials {
+    fn is_empty(&self) -> bool {
+        return self.password.is_empty();
+    }
+}
+
+impl SpotifyCredentials {
+    fn new(spotify: &SpotifyConfig) -> Self {
+        return Self {
+            client_secret: spotify.client_secret.to_owned(),
+            refresh_token: spotify.refresh_token.to_owned(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.client_secret.is_empty() && self.refresh_token.is_empty();
+    }
+}
+
+/// Contents of an encrypted credentials file. The serialized [`Credentials`] are encrypted with XChaCha20-Poly1305,
+/// using a key derived from the passphrase with Argon2id.
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    encrypted: Encrypted,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Encrypted {
+    /// Argon2id parameters, so files keep working if the defaults change
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Where the credentials are read from and written to.
+enum Store {
+    File { encrypt: bool },
+    SecretService(SecretService),
+}
+
+impl Store {
+    /// Picks the backend from `credentials.backend`. `auto` keeps using the backend it last saved to. Before it saved
+    /// anything, it falls back to the file if no Secret Service answers. The Secret Service is looked for on the bus at
+    /// `bus`, or on the session bus if it is `None`.
+    #[instrument(skip_all)]
+    async fn open(config: &CredentialsConfig, bus: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let file: Store = Self::File {
+            encrypt: config.encrypt,
+        };
+        return match (config.backend, config.auto_backend) {
+            (CredentialsBackend::File, _) => Ok(file),
+            (CredentialsBackend::SecretService, _)
+            | (CredentialsBackend::Auto, Some(CredentialsBackend::SecretService)) => {
+                match SecretService::connect(bus).await {
+                    Err(error) => Err(format!("Could not reach the Secret Service, which the credentials are stored in: {error}. Set credentials.backend to \"file\" to use {} instead", file_path()).into()),
+                    Ok(service) => Ok(Self::SecretService(service)),
+                }
+            }
+            (CredentialsBackend::Auto, Some(_)) => Ok(file),
+            (CredentialsBackend::Auto, None) if config.encrypt => Ok(file),
+            (CredentialsBackend::Auto, None) => match SecretService::connect(bus).await {
+                Err(error) => {
+                    warn!(
+                        "No Secret Service available, using {}: {error}",
+                        file_path()
+                    );
+                    Ok(file)
+                }
+                Ok(service) => Ok(Self::SecretService(service)),
+            },
+        };
+    }
+
+    fn backend(&self) -> CredentialsBackend {
+        return match self {
+            Self::File { .. } => CredentialsBackend::File,
+            Self::SecretService(_) => CredentialsBackend::SecretService,
+        };
+    }
+
+    async fn read(&self) -> Result<Credentials, Box<dyn Error>> {
+        return match self {
+            Self::File { encrypt } => {
+                let Some((credentials, encrypted)) = read_file()? else {
+                    return Ok(Credentials::default());
+                };
+                if encrypted != *encrypt && !credentials.is_empty() {
+                    info!(
+                        "{} the credentials file, as credentials.encrypt changed",
+                        if *encrypt { "Encrypting" } else { "Decrypting" }
+                    );
+                    write_file(&credentials, *encrypt)?;
+                }
+                Ok(credentials)
+            }
+            Self::SecretService(service) => service.read().await,
+        };
+    }
+
+    async fn write(&self, credentials: &Credentials) -> Result<(), Box<dyn Error>> {
+        return match self {
+            Self::File { encrypt } => write_file(credentials, *encrypt),
+            Self::SecretService(service) => service.write(credentials).await,
+        };
+    }
+}
+
+/// Fills in the Spotify secrets and the MPD password from the credentials store. Secrets still in `ddrpc.toml` are
+/// kept.
+#[instrument(skip_all)]
+pub async fn load(config: &mut Config) -> Result<(), Box<dyn Error>> {
+    let store: Store = Store::open(&config.credentials, None).await?;
+    let stored: Credentials = store.read().await?;
+    if config.spotify.client_secret.is_empty() {
+        config.spotify.client_secret = stored.spotify.client_secret;
+    }
+    if config.spotify.refresh_token.is_empty() {
+        config.spotify.refresh_token = stored.spotify.refresh_token;
+    }
+    if config.mpd.password.is_empty() {
+        config.mpd.password = stored.mpd.password;
+    }
+    trace!("Loaded the credentials");
+    return Ok(());
+}
+
+/// Saves the Spotify secrets to the credentials store, replacing the stored ones.
+pub async fn save(
+    config: &CredentialsConfig,
+    spotify: &SpotifyConfig,
+) -> Result<(), Box<dyn Error>> {
+    return update(config, |credentials: &mut Credentials| {
+        credentials.spotify = SpotifyCredentials::new(spotify);
+    })
+    .await;
+}
+
+/// Saves the MPD password to the credentials store, replacing the stored one.
+pub async fn save_mpd(config: &CredentialsConfig, mpd: &MpdConfig) -> Result<(), Box<dyn Error>> {
+    return update(config, |credentials: &mut Credentials| {
+        credentials.mpd = MpdCredentials {
+            password: mpd.password.to_owned(),
+        };
+    })
+    .await;
+}
+
+/// Applies `change` to the stored credentials and writes them if they changed. Secrets `change` leaves alone are kept.
+#[instrument(skip_all)]
+async fn update(
+    config: &CredentialsConfig,
+    change: impl FnOnce(&mut Credentials),
+) -> Result<(), Box<dyn Error>> {
+    let store: Store = Store::open(config, None).await?;
+    let stored: Credentials = store.read().await?;
+    let mut credentials: Credentials = stored.to_owned();
+    change(&mut credentials);
+    if credentials == stored {
+        trace!("Credentials unchanged");
+        return Ok(());
+    }
+
+    store.write(&credentials).await?;
+    trace!("Saved the credentials");
+
+    if config.backend == CredentialsBackend::Auto && config.auto_backend != Some(store.backend()) {
+        debug!(
+            "Credentials saved to {:?}, keeping it as the backend",
+            store.backend()
+        );
+        write_config(&CredentialsConfig {
+            auto_backend: Some(store.backend()),
+            ..config.to_owned()
+        })?;
+    }
+    return Ok(());
+}
+
+/// Moves secrets left in `ddrpc.toml` by earlier versions or added by the user to the credentials store and removes
+/// them from the file.
+#[instrument(skip_all)]
+pub async fn migrate(config: &mut Config) -> Result<(), Box<dyn Error>> {
+    let spotify: bool =
+        !config.spotify.client_secret.is_empty() || !config.spotify.refresh_token.is_empty();
+    let mpd: bool = !config.mpd.password.is_empty();
+    if !spotify && !mpd {
+        return Ok(());
+    }
+
+    let mut loaded: Config = config.to_owned();
+    load(&mut loaded).await?;
+    if spotify {
+        save(&config.credentials, &loaded.spotify).await?;
+        write_config(&config.spotify)?;
+        info!("Moved the Spotify secrets from ddrpc.toml to the credentials store");
+    }
+    if mpd {
+        save_mpd(&config.credentials, &loaded.mpd).await?;
+        write_config(&config.mpd)?;
+        info!("Moved the MPD password from ddrpc.toml to the credentials store");
+    }
+    config.spotify = loaded.spotify;
+    config.mpd = loaded.mpd;
+    return Ok(());
+}
+
+/// Reads the credentials file. Returns `None` if there is none, and whether it was encrypted otherwise. Files other
+/// users can access are refused, as the secrets in them may have been read already.
+#[instrument(skip_all)]
+fn read_file() -> Result<Option<(Credentials, bool)>, Box<dyn Error>> {
+    let path: String = file_path();
+    let mode: u32 = match fs::metadata(&path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            trace!("No credentials file at {path}");
+            return Ok(None);
+        }
+        Err(error) => return Err(error.into()),
+        Ok(metadata) => metadata.permissions().mode() & 0o777,
+    };
+    if mode & 0o077 != 0 {
+        return Err(format!("{path} is accessible by other users (mode {mode:o}). Restrict it with `chmod 600 {path}`, and replace the secrets in it if others could have read them").into());
+    }
+
+    let contents: String = fs::read_to_string(&path)?;
+    let table: Table = from_str(&contents)?;
+    if !table.contains_key("encrypted") {
+        return Ok(Some((from_str(&contents)?, false)));
+    }
+
+    let file: EncryptedFile = from_str(&contents)?;
+    let plaintext: String = decrypt(&file.encrypted, passphrase()?)?;
+    return Ok(Some((from_str(&plaintext)?, true)));
+}
+
+/// Replaces the credentials file through a new file created with mode 600, so the secrets are never readable by others
+/// and a failed write does not lose the old ones. The file is removed if there are no credentials.
+#[instrument(skip_all)]
+fn write_file(credentials: &Credentials, encrypt: bool) -> Result<(), Box<dyn Error>> {
+    let path: String = file_path();
+    if credentials.is_empty() {
+        if Path::new(&path).exists() {
+            fs::remove_file(&path)?;
+            trace!("Removed {path}");
+        }
+        return Ok(());
+    }
+
+    let plaintext: String = to_string(credentials)?;
+    let contents: String = match encrypt {
+        false => plaintext,
+        true => to_string(&EncryptedFile {
+            encrypted: encrypt_credentials(&plaintext, passphrase()?)?,
+        })?,
+    };
+
+    let config_dir: String = dir_path();
+    if !Path::new(&config_dir).exists() {
+        fs::create_dir_all(&config_dir)?;
+        trace!("Created config directory {config_dir}");
+    }
+
+    let temporary: String = path.to_owned() + ".tmp";
+    if Path::new(&temporary).exists() {
+        fs::remove_file(&temporary)?;
+    }
+    let mut file: fs::File = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary, &path)?;
+    trace!("Wrote to file {path}");
+    return Ok(());
+}
+
+/// Passphrase from [`PASSPHRASE_VARIABLE`], or asked for if ddrpc runs in a terminal.
+fn passphrase() -> Result<&'static str, Box<dyn Error>> {
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+
+    let passphrase: String = match env::var(PASSPHRASE_VARIABLE) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ if std::io::stdin().is_terminal() => {
+            rpassword::prompt_password("Credentials passphrase: ")?
+        }
+        _ => {
+            return Err(format!(
+                "Set {PASSPHRASE_VARIABLE} to the passphrase of the encrypted credentials file"
+            )
+            .into())
+        }
+    };
+    if passphrase.is_empty() {
+        return Err("The credentials passphrase cannot be empty".into());
+    }
+    return Ok(PASSPHRASE.get_or_init(|| passphrase));
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: Params,
+) -> Result<[u8; KEY_LENGTH], Box<dyn Error>> {
+    let mut key: [u8; KEY_LENGTH] = [0; KEY_LENGTH];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|error: argon2::Error| format!("Could not derive the credentials key: {error}"))?;
+    return Ok(key);
+}
+
+/// Encrypts with a new salt and nonce every time.
+fn encrypt_credentials(plaintext: &str, passphrase: &str) -> Result<Encrypted, Box<dyn Error>> {
+    let mut salt: [u8; SALT_LENGTH] = [0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let params: Params = Params::default();
+    let key: [u8; KEY_LENGTH] = derive_key(passphrase, &salt, params.to_owned())?;
+
+    let nonce: XNonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext: Vec<u8> = XChaCha20Poly1305::new(&key.into())
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| "Could not encrypt the credentials")?;
+
+    return Ok(Encrypted {
+        memory_cost: params.m_cost(),
+        time_cost: params.t_cost(),
+        parallelism: params.p_cost(),
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    });
+}
+
+fn decrypt(encrypted: &Encrypted, passphrase: &str) -> Result<String, Box<dyn Error>> {
+    let params: Params = Params::new(
+        encrypted.memory_cost,
+        encrypted.time_cost,
+        encrypted.parallelism,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|error: argon2::Error| format!("Invalid credentials key parameters: {error}"))?;
+    let key: [u8; KEY_LENGTH] = derive_key(passphrase, &STANDARD.decode(&encrypted.salt)?, params)?;
+
+    let nonce: Vec<u8> = STANDARD.decode(&encrypted.nonce)?;
+    if nonce.len() != 24 {
+        return Err("Invalid credentials nonce".into());
+    }
+    let plaintext: Vec<u8> = XChaCha20Poly1305::new(&key.into())
+        .decrypt(XNonce::from_slice(&nonce), STANDARD.decode(&encrypted.ciphertext)?.as_slice())
+        .map_err(|_| "Could not decrypt the credentials file, the passphrase is wrong or the file was changed")?;
+    return Ok(String::from_utf8(plaintext)?);
+}
+
+/// Session with the Secret Service. Secrets are transferred unencrypted, which is fine on the session bus since only
+/// the user can connect to it.
+struct SecretService {
+    connection: Connection,
+    session: OwnedObjectPath,
+}
+
+impl SecretService {
+    /// Opens a session on the bus at `bus`, or on the session bus if it is `None`.
+    async fn connect(bus: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let connection: Connection = match bus {
+            None => Connection::session().await?,
+            Some(bus) => connection::Builder::address(bus)?.build().await?,
+        };
+        let (_, session): (OwnedValue, OwnedObjectPath) = timeout(
+            SECRET_SERVICE_TIMEOUT,
+            connection.call_method(
+                Some(SECRETS_SERVICE),
+                SECRETS_PATH,
+                Some(SERVICE_INTERFACE),
+                "OpenSession",
+                &("plain", Value::from("")),
+            ),
+        )
+        .await
+        .map_err(|_| "The Secret Service did not answer")??
+        .body()
+        .deserialize()?;
+        debug!("Opened a Secret Service session");
+        return Ok(Self {
+            connection,
+            session,
+        });
+    }
+
+    /// The keyring item holding the credentials, if there is one.
+    async fn item(&self) -> Result<Option<OwnedObjectPath>, Box<dyn Error>> {
+        let attributes: HashMap<&str, &str> = HashMap::from(ITEM_ATTRIBUTES);
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = self
+            .connection
+            .call_method(
+                Some(SECRETS_SERVICE),
+                SECRETS_PATH,
+                Some(SERVICE_INTERFACE),
+                "SearchItems",
+                &(attributes,),
+            )
+            .await?
+            .body()
+            .deserialize()?;
+
+        if let Some(item) = unlocked.into_iter().next() {
+            return Ok(Some(item));
+        }
+        if !locked.is_empty() {
+            return Err(
+                "The keyring holding the ddrpc credentials is locked, unlock it and try again"
+                    .into(),
+            );
+        }
+        return Ok(None);
+    }
+
+    async fn read(&self) -> Result<Credentials, Box<dyn Error>> {
+        let Some(item) = self.item().await? else {
+            trace!("No ddrpc credentials in the keyring");
+            return Ok(Credentials::default());
+        };
+
+        let (_, _, secret, _): (OwnedObjectPath, Vec<u8>, Vec<u8>, String) = self
+            .connection
+            .call_method(
+                Some(SECRETS_SERVICE),
+                &item,
+                Some(ITEM_INTERFACE),
+                "GetSecret",
+                &(&self.session,),
+            )
+            .await?
+            .body()
+            .deserialize()?;
+        return Ok(from_str(&String::from_utf8(secret)?)?);
+    }
+
+    /// Replaces the keyring item in the default collection, or removes it if there are no credentials.
+    async fn write(&self, credentials: &Credentials) -> Result<(), Box<dyn Error>> {
+        if credentials.is_empty() {
+            if let Some(item) = self.item().await? {
+                self.connection
+                    .call_method(
+                        Some(SECRETS_SERVICE),
+                        &item,
+                        Some(ITEM_INTERFACE),
+                        "Delete",
+                        &(),
+                    )
+                    .await?;
+                trace!("Removed the ddrpc credentials from the keyring");
+            }
+            return Ok(());
+        }
+
+        let collection: OwnedObjectPath = self
+            .connection
+            .call_method(
+                Some(SECRETS_SERVICE),
+                SECRETS_PATH,
+                Some(SERVICE_INTERFACE),
+                "ReadAlias",
+                &("default",),
+            )
+            .await?
+            .body()
+            .deserialize()?;
+        if collection.as_str() == "/" {
+            return Err("The Secret Service has no default keyring".into());
+        }
+
+        let properties: HashMap<&str, Value> = HashMap::from([
+            ("org.freedesktop.Secret.Item.Label", Value::from(ITEM_LABEL)),
+            (
+                "org.freedesktop.Secret.Item.Attributes",
+                Value::from(HashMap::from(ITEM_ATTRIBUTES)),
+            ),
+        ]);
+        let secret: (&OwnedObjectPath, Vec<u8>, Vec<u8>, &str) = (
+            &self.session,
+            Vec::new(),
+            to_string(credentials)?.into_bytes(),
+            "text/plain",
+        );
+        let (item, _prompt): (OwnedObjectPath, OwnedObjectPath) = self
+            .connection
+            .call_method(
+                Some(SECRETS_SERVICE),
+                &collection,
+                Some(COLLECTION_INTERFACE),
+                "CreateItem",
+                &(properties, secret, true),
+            )
+            .await?
+            .body()
+            .deserialize()?;
+        // The service asks for a prompt instead of creating the item if the keyring is locked
+        if item.as_str() == "/" {
+            return Err("The default keyring is locked, unlock it and try again".into());
+        }
+        trace!("Saved the ddrpc credentials to the keyring");
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_credentials_round_trip() {
+        let encrypted: Encrypted = encrypt_credentials("secret = 1", "passphrase").unwrap();
+        assert!(!STANDARD
+            .decode(&encrypted.ciphertext)
+            .unwrap()
+            .windows(6)
+            .any(|window: &[u8]| window == b"secret"));
+        assert_eq!(decrypt(&encrypted, "passphrase").unwrap(), "secret = 1");
+
+        let error: Box<dyn Error> = decrypt(&encrypted, "wrong").unwrap_err();
+        assert!(error.to_string().contains("passphrase is wrong"), "{error}");
+
+        // A new salt and nonce are used every time
+        let again: Encrypted = encrypt_credentials("secret = 1", "passphrase").unwrap();
+        assert_ne!(again.salt, encrypted.salt);
+        assert_ne!(again.nonce, encrypted.nonce);
+    }
+
+    #[test]
+    fn credentials_leave_out_empty_secrets() {
+        let mut spotify: SpotifyConfig = Config::default().spotify;
+        let mut credentials: Credentials = Credentials {
+            spotify: SpotifyCredentials::new(&spotify),
+            ..Credentials::default()
+        };
+        assert!(credentials.is_empty());
+
+        spotify.refresh_token = String::from("token");
+        credentials.spotify = SpotifyCredentials::new(&spotify);
+        assert!(!credentials.is_empty());
+        assert_eq!(
+            to_string(&credentials).unwrap(),
+            "[spotify]\nrefresh_token = \"token\"\n"
+        );
+
+        credentials.mpd.password = String::from("password");
+        assert_eq!(
+            to_string(&credentials).unwrap(),
+            "[mpd]\npassword = \"password\"\n\n[spotify]\nrefresh_token = \"token\"\n"
+        );
+        assert!(from_str::<Credentials>("").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn auto_keeps_the_backend_it_saved_to() {
+        let config: CredentialsConfig = CredentialsConfig {
+            auto_backend: Some(CredentialsBackend::File),
+            ..CredentialsConfig::default()
+        };
+        let bus: Option<&str> = Some("unix:path=/nonexistent/ddrpc-test-bus");
+        let store: Store = Store::open(&config, bus).await.unwrap();
+        assert_eq!(store.backend(), CredentialsBackend::File);
+
+        // Secrets saved to the keyring are not looked for in the file when the keyring is unavailable
+        let config: CredentialsConfig = CredentialsConfig {
+            auto_backend: Some(CredentialsBackend::SecretService),
+            ..CredentialsConfig::default()
+        };
+        let error: Box<dyn Error> = match Store::open(&config, bus).await {
+            Err(error) => error,
+            Ok(store) => panic!("Opened {:?}", store.backend()),
+        };
+        assert!(
+            error
+                .to_string()
+                .contains("Could not reach the Secret Service"),
+            "{error}"
+        );
+    }
+}
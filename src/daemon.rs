@@ -0,0 +1,200 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, DirBuilder, Metadata},
+    io::{ErrorKind, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt},
+        net,
+    },
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    net::{UnixListener, UnixStream},
+    time::timeout,
+};
+
+/// Senders write a whole message at once, so a short timeout only guards against misbehaving clients
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Messages other `ddrpc` commands send to the running presence loop. Each message is one line of JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonMessage {
+    TaskStarted {
+        /// PID of the `ddrpc run` process, used to match the finished message
+        id: u32,
+        name: String,
+        details: Option<String>,
+        command: String,
+        /// Unix timestamp in seconds
+        started: u64,
+    },
+    TaskFinished {
+        id: u32,
+        exit_code: i32,
+        /// Unix timestamp in seconds
+        finished: u64,
+    },
+}
+
+/// Directory for files only the current user may access, such as the daemon socket and shell state. Uses
+/// `$XDG_RUNTIME_DIR`, or a `ddrpc-<uid>` directory in the temporary directory if it is not set. The fallback is created
+/// with mode 0700 and refused if it belongs to another user, is a symlink or other users can access it.
+pub fn runtime_dir() -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(dir) = dirs::runtime_dir() {
+        return Ok(dir);
+    }
+
+    let uid: u32 = uid();
+    return private_dir(std::env::temp_dir().join(format!("ddrpc-{uid}")), uid);
+}
+
+/// Effective user ID of this process.
+pub fn uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    return unsafe { libc::geteuid() };
+}
+
+/// Creates `dir` with mode 0700 if it does not exist. Fails if it is not a directory owned by `uid` that only its owner
+/// can access.
+pub fn private_dir(dir: PathBuf, uid: u32) -> Result<PathBuf, Box<dyn Error>> {
+    if let Err(error) = DirBuilder::new().mode(0o700).create(&dir) {
+        if error.kind() != ErrorKind::AlreadyExists {
+            return Err(error.into());
+        }
+    }
+
+    let metadata: Metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} is not a private directory owned by the current user. Remove it to have it created again",
+            dir.display()
+        )
+        .into());
+    }
+    return Ok(dir);
+}
+
+/// Path of the socket the presence loop listens on, in [`runtime_dir`].
+pub fn socket_path() -> Result<PathBuf, Box<dyn Error>> {
+    return Ok(runtime_dir()?.join("ddrpc.sock"));
+}
+
+/// Socket the presence loop receives [`DaemonMessage`]s on. Connections are accepted by its own task with
+/// [`DaemonListener::accept`] and each one is read by a separate task with [`read_messages`].
+pub struct DaemonListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl DaemonListener {
+    /// Binds the socket at [`socket_path`]. A leftover socket from a loop that did not exit cleanly is replaced, but one
+    /// that another running loop still accepts connections on is not.
+    #[instrument(skip_all)]
+    pub fn bind() -> Result<Self, Box<dyn Error>> {
+        let path: PathBuf = socket_path()?;
+        if path.exists() {
+            if net::UnixStream::connect(&path).is_ok() {
+                return Err(
+                    format!("Another ddrpc instance is listening on {}", path.display()).into(),
+                );
+            }
+            fs::remove_file(&path)?;
+        }
+
+        let listener: UnixListener = UnixListener::bind(&path)?;
+        debug!("Listening on {}", path.display());
+
+        return Ok(Self { listener, path });
+    }
+
+    /// Waits for the next connection. Returns `None` if accepting it failed.
+    pub async fn accept(&self) -> Option<UnixStream> {
+        return match self.listener.accept().await {
+            Err(error) => {
+                warn!("Could not accept socket connection: {error}");
+                None
+            }
+            Ok((stream, _)) => Some(stream),
+        };
+    }
+}
+
+impl Drop for DaemonListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the messages sent on one connection until it is closed. Invalid messages are logged and skipped.
+pub async fn read_messages(stream: UnixStream) -> Vec<DaemonMessage> {
+    let mut messages: Vec<DaemonMessage> = Vec::new();
+    let mut lines: Lines<BufReader<UnixStream>> = BufReader::new(stream).lines();
+    loop {
+        let line: String = match timeout(READ_TIMEOUT, lines.next_line()).await {
+            Err(_) => {
+                warn!("Timed out reading socket message");
+                break;
+            }
+            Ok(Err(error)) => {
+                warn!("Could not read socket message: {error}");
+                break;
+            }
+            Ok(Ok(None)) => break,
+            Ok(Ok(Some(line))) => line,
+        };
+        match serde_json::from_str::<DaemonMessage>(&line) {
+            Err(error) => warn!("Invalid socket message \"{line}\": {error}"),
+            Ok(message) => {
+                trace!("Received {message:?}");
+                messages.push(message);
+            }
+        }
+    }
+
+    return messages;
+}
+
+/// Sends a message to the running presence loop. Fails if no loop is running.
+pub fn send_message(message: &DaemonMessage) -> Result<(), Box<dyn Error>> {
+    let mut stream: net::UnixStream = net::UnixStream::connect(socket_path()?)?;
+    let mut line: String = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn private_dir_permissions() {
+        let uid: u32 = uid();
+        let base: PathBuf =
+            std::env::temp_dir().join(format!("ddrpc-test-{}-private", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(&base).unwrap();
+
+        let created: PathBuf = private_dir(base.join("new"), uid).unwrap();
+        assert_eq!(fs::metadata(&created).unwrap().mode() & 0o777, 0o700);
+        assert!(private_dir(created, uid).is_ok());
+
+        let shared: PathBuf = base.join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(shared.to_owned(), uid).is_err());
+
+        let link: PathBuf = base.join("link");
+        symlink(base.join("new"), &link).unwrap();
+        assert!(private_dir(link, uid).is_err());
+
+        assert!(private_dir(base.join("new"), uid + 1).is_err());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use crate::prelude::*;
+use dirs::{data_dir, home_dir};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Application data parsed from the `[Desktop Entry]` group of an XDG `.desktop` file.
+#[derive(Clone, Debug)]
+pub struct DesktopEntry {
+    /// Desktop file ID, the file name without the `.desktop` extension
+    pub id: String,
+    pub name: String,
+    pub exec: String,
+    pub icon: String,
+    /// Application ID from the `X-Flatpak` key, only set for Flatpak exports
+    pub flatpak_id: Option<String>,
+}
+
+impl DesktopEntry {
+    /// Name the process started by this entry will have, as it is reported by `sysinfo`. Returns `None` if it cannot be
+    /// determined from the `Exec` key.
+    pub fn process_name(&self) -> Option<String> {
+        let args: Vec<String> = exec_args(&self.exec);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        let mut args = args.iter().peekable();
+        if args.peek() == Some(&&"env") {
+            args.next();
+            while args.peek().is_some_and(|arg: &&&str| arg.contains('=')) {
+                args.next();
+            }
+        }
+
+        let binary: &str = args.next()?;
+        let binary: &str = match Path::new(binary).file_name() {
+            None => return None,
+            Some(file_name) => file_name.to_str()?,
+        };
+
+        if binary == "flatpak" {
+            // The process inside the sandbox is the command the Flatpak was started with
+            let command: &str = args.find_map(|arg: &&str| arg.strip_prefix("--command="))?;
+            return Some(short_process_name(command));
+        }
+
+        return Some(short_process_name(binary));
+    }
+}
+
+/// Splits an `Exec` value into arguments. Arguments can be double quoted to contain spaces, and `\` escapes the next
+/// character inside quotes.
+fn exec_args(exec: &str) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quoted: bool = false;
+    let mut chars = exec.chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            char if char.is_whitespace() && !quoted => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            char => arg.get_or_insert_with(String::new).push(char),
+        }
+    }
+    if let Some(arg) = arg {
+        args.push(arg);
+    }
+    return args;
+}
+
+/// Linux limits process names to 15 bytes, so longer binary names are truncated the same way.
+fn short_process_name(name: &str) -> String {
+    if cfg!(target_os = "linux") {
+        let mut end: usize = name.len().min(15);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        return name[..end].to_owned();
+    }
+    return name.to_owned();
+}
+
+/// Directories searched for `.desktop` files, from highest to lowest precedence.
+pub fn application_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+
+    if let Some(data_dir) = data_dir() {
+        dirs.push(data_dir.join("applications"));
+        dirs.push(data_dir.join("flatpak/exports/share/applications"));
+    } else if let Some(home_dir) = home_dir() {
+        dirs.push(home_dir.join(".local/share/applications"));
+    }
+
+    let data_dirs: String = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|data_dirs: &String| !data_dirs.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+    for data_dir in data_dirs.split(':') {
+        dirs.push(Path::new(data_dir).join("applications"));
+    }
+
+    let flatpak_dir: PathBuf = PathBuf::from("/var/lib/flatpak/exports/share/applications");
+    if !dirs.contains(&flatpak_dir) {
+        dirs.push(flatpak_dir);
+    }
+
+    return dirs;
+}
+
+/// Reads all visible application entries from [`application_dirs`]. If the same desktop file ID exists in multiple
+/// directories, only the one with the highest precedence is used.
+#[instrument(skip_all)]
+pub fn installed_applications() -> Vec<DesktopEntry> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut entries: Vec<DesktopEntry> = Vec::new();
+
+    for dir in application_dirs() {
+        let Ok(files) = fs::read_dir(&dir) else {
+            trace!("Skipping application directory {}", dir.display());
+            continue;
+        };
+
+        for file in files.flatten() {
+            let path: PathBuf = file.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("desktop") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if !seen.insert(id.to_owned()) {
+                continue;
+            }
+
+            match fs::read_to_string(&path) {
+                Err(error) => debug!("Could not read {}: {error}", path.display()),
+                Ok(contents) => {
+                    if let Some(entry) = parse_desktop_entry(id, &contents) {
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+    }
+
+    debug!("Found {} installed applications", entries.len());
+    return entries;
+}
+
+/// Parses the `[Desktop Entry]` group of a `.desktop` file. Returns `None` for hidden entries and entries that are not
+/// applications.
+fn parse_desktop_entry(id: &str, contents: &str) -> Option<DesktopEntry> {
+    let mut in_entry: bool = false;
+    let mut entry: DesktopEntry = DesktopEntry {
+        id: id.to_owned(),
+        name: String::new(),
+        exec: String::new(),
+        icon: String::new(),
+        flatpak_id: None,
+    };
+
+    for line in contents.lines() {
+        let line: &str = line.trim();
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_entry || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let value: String = value.trim().to_owned();
+        match key.trim() {
+            "Type" if value != "Application" => return None,
+            "Hidden" | "NoDisplay" if value == "true" => return None,
+            "Name" => entry.name = value,
+            "Exec" => entry.exec = value,
+            "Icon" => entry.icon = value,
+            "X-Flatpak" => entry.flatpak_id = Some(value),
+            _ => (),
+        }
+    }
+
+    if entry.name.is_empty() || entry.exec.is_empty() {
+        return None;
+    }
+    return Some(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(exec: &str) -> DesktopEntry {
+        return DesktopEntry {
+            id: String::from("test"),
+            name: String::from("Test"),
+            exec: exec.to_owned(),
+            icon: String::new(),
+            flatpak_id: None,
+        };
+    }
+
+    #[test]
+    fn process_name_from_exec() {
+        let cases: [(&str, Option<&str>); 9] = [
+            ("firefox %u", Some("firefox")),
+            ("/usr/bin/code --unity-launch %F", Some("code")),
+            ("\"/opt/My App/bin/app\" %U", Some("app")),
+            ("env GDK_BACKEND=x11 FOO=bar /usr/bin/gimp %U", Some("gimp")),
+            ("env /usr/bin/blender", Some("blender")),
+            (
+                "/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=obs com.obsproject.Studio",
+                Some("obs"),
+            ),
+            ("flatpak run org.gnome.Calculator", None),
+            ("gnome-system-monitor", Some("gnome-system-mo")),
+            ("", None),
+        ];
+
+        for (exec, expected) in cases {
+            assert_eq!(
+                entry(exec).process_name().as_deref(),
+                expected,
+                "Exec={exec}"
+            );
+        }
+    }
+
+    #[test]
+    fn exec_args_handles_quotes() {
+        assert_eq!(exec_args("a  b\tc"), ["a", "b", "c"]);
+        assert_eq!(
+            exec_args("\"/opt/My App/app\" --x"),
+            ["/opt/My App/app", "--x"]
+        );
+        assert_eq!(
+            exec_args("sh -c \"echo \\\"hi\\\"\""),
+            ["sh", "-c", "echo \"hi\""]
+        );
+        assert_eq!(exec_args("app \"\""), ["app", ""]);
+    }
+
+    #[test]
+    fn short_process_name_keeps_char_boundaries() {
+        assert_eq!(short_process_name("code"), "code");
+        assert_eq!(short_process_name("exactly15bytess"), "exactly15bytess");
+        assert_eq!(short_process_name("sixteen-bytes-xx"), "sixteen-bytes-x");
+        // 14 ASCII bytes and a 2 byte character crossing the limit
+        assert_eq!(short_process_name("abcdefghijklmné"), "abcdefghijklmn");
+    }
+}
@@ -1,30 +1,49 @@
 use crate::{
-    parser::{variables::template_hashmap, CliDiscordSet},
+    credentials,
+    daemon::DaemonListener,
+    hooks::{run_hooks, HookEvent},
+    media::{current_media, media_providers, provider_variables, MediaData},
+    parser::{
+        variables::{template_hashmap, template_variables},
+        CliDiscordSet,
+    },
     prelude::*,
-    spotify,
+    runtime::{Inputs, Update},
+    schedule::Demand,
+    shell::ShellMonitor,
+    spotify::{self, SpotifyClient},
+    task::{current_task, TaskState},
 };
 use discord_rich_presence::{activity::*, DiscordIpc, DiscordIpcClient};
-use rspotify::AuthCodeSpotify;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-/// Bundle DiscordIpcClient and the Discord activity data associated to it.
+// Bundle DiscordIpcClient and the Discord activity data associated to it.
 // pub struct ClientBundle {
 //     pub discord: DiscordIpcClient,
 //     pub new_data: DiscordConfig,
 //     pub spotify: AuthCodeSpotify,
 // }
 
+/// The last rendered activity, kept by the renderer to only hand changed activities to the Discord sender.
 pub struct DiscordState {
-    pub client: DiscordIpcClient,
     /// From [`DiscordConfig`] with all fields parsed with [`DiscordConfig::replace_templates`];
     pub prev_data: DiscordConfig,
+    /// Timestamps of the last activity, set while a `ddrpc run` task is running or media is playing
+    pub prev_timestamps: Option<Timespan>,
+    /// Template variables the last activity was rendered with. Empty until the first render
+    pub variables: HashMap<String, String>,
 }
 
 impl DiscordState {
-    fn new(client: DiscordIpcClient, client_id: u64) -> Self {
+    pub fn new(client_id: u64) -> Self {
         Self {
-            client,
             prev_data: DiscordConfig::new(client_id),
+            prev_timestamps: None,
+            variables: HashMap::new(),
         }
     }
 }
@@ -98,41 +117,175 @@ pub fn set_activity_data(
 
 /// Initialize and connect `DiscordIpcClient`.
 #[instrument(skip_all)]
-pub async fn client_init(config: &mut Config) -> Result<AppState, Box<dyn Error>> {
+pub async fn client_init(
+    config: &mut Config,
+    no_browser: bool,
+) -> Result<AppState, Box<dyn Error>> {
     let mut client: DiscordIpcClient =
         DiscordIpcClient::new(&config.discord.client_id.to_string())?;
     trace!("Successfully initialized Discord client");
 
     client.connect()?;
     info!("Discord client connected to IPC");
+    run_hooks(&config.hooks, &HookEvent::DiscordConnected);
+
+    let demand: Demand = Demand::new(config);
+    let uses_source = |name: &str| -> bool {
+        return demand.media
+            && config
+                .media
+                .order
+                .iter()
+                .any(|source: &String| source == name);
+    };
+    let uses_spotify: bool = uses_source("spotify_api");
+    // Only opened if a source needs a secret, as it can ask for the passphrase or start a keyring
+    if uses_spotify || uses_source("mpd") {
+        credentials::load(config).await?;
+    }
+    let spotify_client: Option<SpotifyClient> = match uses_spotify {
+        false => {
+            debug!("Not using the Spotify Web API, no media variables are used or spotify_api is not in media.order");
+            None
+        }
+        true => {
+            let scopes: HashSet<String> = spotify::required_scopes(&template_variables(config));
+            spotify::client_init(&mut config.spotify, &config.credentials, scopes, no_browser)
+                .await?
+        }
+    };
 
-    let spotify_client: Option<AuthCodeSpotify> = spotify::client_init(&mut config.spotify).await?;
-
-    return Ok(AppState::new(DiscordState::new(client, 0), spotify_client));
+    let daemon: Option<DaemonListener> = match DaemonListener::bind() {
+        Err(error) => {
+            warn!("Could not listen for ddrpc run tasks: {error}");
+            None
+        }
+        Ok(daemon) => Some(daemon),
+    };
+
+    return Ok(AppState::new(
+        daemon,
+        demand.to_owned(),
+        client,
+        match demand.media {
+            false => Vec::new(),
+            true => media_providers(config, spotify_client.as_ref()).await,
+        },
+        ShellMonitor::new(&config.shell),
+        spotify_client,
+    ));
 }
 
-/// Set Discord activity. Will clone `DiscordConfig` data and replace template variables before comparing to old data. If the new data matches<br/>
-/// with the old data, the function will return. Otherwise, the new data is used and the activity will be overwritten.
+/// Renders the activity from the latest inputs. Will clone `DiscordConfig` data and replace template variables before
+/// comparing to the last rendered data. Returns the new activity data and timestamps if they changed, `None` if they
+/// did not.
 #[instrument(skip_all)]
-pub async fn set_activity(
+pub async fn render_activity(
     config: &Config,
+    inputs: &mut Inputs,
     discord: &mut DiscordState,
-    spotify: &Option<AuthCodeSpotify>,
-) -> Result<(), Box<dyn Error>> {
+) -> Option<(DiscordConfig, Option<Timespan>)> {
     let mut new_data: DiscordConfig = config.discord.clone();
     trace!("Discord data cloned");
 
-    let template_hashmap: HashMap<String, String> = template_hashmap(config, spotify).await;
+    let task: Option<&TaskState> = current_task(&config.task, &mut inputs.tasks);
+
+    let mut media: MediaData = current_media(&inputs.media)
+        .unwrap_or_else(|| MediaData::fallback(&config.spotify.fallback));
+    media.variables.extend(provider_variables(&inputs.media));
+    let timestamps: Option<Timespan> = match task {
+        Some(task) if task.result.is_none() => Some((task.started, None)),
+        Some(_) => None,
+        None => media_timestamps(config, &media),
+    };
+
+    let template_hashmap: HashMap<String, String> = template_hashmap(
+        config,
+        &inputs.processes,
+        inputs.auto_app.as_ref(),
+        media,
+        inputs.shell.as_ref(),
+        inputs.workspace.to_owned(),
+        task,
+    )
+    .await;
+
+    let first_render: bool = discord.variables.is_empty();
+    if !first_render && track_changed(&discord.variables, &template_hashmap) {
+        run_hooks(
+            &config.hooks,
+            &HookEvent::TrackChanged(track_variables(&template_hashmap)),
+        );
+    }
+    discord.variables = template_hashmap.to_owned();
+
+    if let Some(task) = task {
+        let details: &String = task.details.as_ref().unwrap_or(&config.task.details);
+        let state: &String = match task.result {
+            None => &config.task.state,
+            Some(_) => &config.task.finished_state,
+        };
+        if !details.is_empty() {
+            new_data.details = details.to_owned();
+        }
+        if !state.is_empty() {
+            new_data.state = state.to_owned();
+        }
+    }
 
     new_data.replace_templates(&template_hashmap);
 
-    if new_data == discord.prev_data {
+    if new_data == discord.prev_data && same_timestamps(timestamps, discord.prev_timestamps) {
         trace!("Activity data has not changed");
-        return Ok(());
+        return None;
     }
 
     info!("Activity data has changed, overwriting and setting activity");
+    if !first_render {
+        run_hooks(
+            &config.hooks,
+            &HookEvent::ActivityChanged(discord.prev_data.to_owned(), new_data.to_owned()),
+        );
+    }
+    discord.prev_data = new_data.to_owned();
+    discord.prev_timestamps = timestamps;
+
+    return Some((new_data, timestamps));
+}
 
+/// What the renderer hands to the Discord sender.
+#[allow(clippy::large_enum_variant)]
+pub enum DiscordCommand {
+    Set(DiscordConfig, Option<Timespan>),
+    Clear,
+}
+
+/// Sets or clears the activity for every command from the renderer, until the renderer stops. Discord IPC blocks, so
+/// this runs on a blocking thread. A failure is published as [`Update::DiscordFailed`] and stops the sender.
+pub fn send_activities(
+    mut client: DiscordIpcClient,
+    mut commands: UnboundedReceiver<DiscordCommand>,
+    updates: UnboundedSender<Update>,
+) -> () {
+    while let Some(command) = commands.blocking_recv() {
+        let result: Result<(), Box<dyn Error>> = match command {
+            DiscordCommand::Set(data, timestamps) => send_activity(&mut client, &data, timestamps),
+            DiscordCommand::Clear => clear_activity(&mut client),
+        };
+        if let Err(error) = result {
+            let _ = updates.send(Update::DiscordFailed(error.to_string()));
+            return;
+        }
+    }
+}
+
+/// Set Discord activity from rendered data.
+#[instrument(skip_all)]
+fn send_activity(
+    client: &mut DiscordIpcClient,
+    new_data: &DiscordConfig,
+    timestamps: Option<Timespan>,
+) -> Result<(), Box<dyn Error>> {
     let mut activity: Activity = Activity::new();
 
     if !new_data.details.is_empty() {
@@ -185,35 +338,223 @@ pub async fn set_activity(
         activity = activity.buttons(buttons);
     }
 
-    discord.client.set_activity(activity)?;
+    if let Some((start, end)) = timestamps {
+        let mut activity_timestamps: Timestamps = Timestamps::new().start(start as i64);
+        if let Some(end) = end {
+            activity_timestamps = activity_timestamps.end(end as i64);
+        }
+        activity = activity.timestamps(activity_timestamps);
+    }
 
-    debug!("Activity set to: \n{new_data:?}");
-    discord.prev_data = new_data.to_owned();
+    client.set_activity(activity)?;
 
+    debug!("Activity set to: \n{new_data:?}");
     return Ok(());
 }
 
+/// Start and optional end of an activity as Unix timestamps in seconds.
+pub type Timespan = (u64, Option<u64>);
+
+/// Seconds the derived media timestamps may drift before the activity is updated. Positions are only read on every
+/// update, so small differences are request latency rather than seeks.
+const TIMESTAMP_TOLERANCE: u64 = 2;
+
+/// Timestamps that make Discord's progress bar match the playing media, if `media.timestamps` is enabled. `None` while
+/// paused or if the source has no length.
+fn media_timestamps(config: &Config, media: &MediaData) -> Option<Timespan> {
+    if !config.media.timestamps || media.status != "playing" || media.length == 0 {
+        return None;
+    }
+    let now: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+    let start: u64 = now.saturating_sub(media.position);
+    return Some((start / 1000, Some((start + media.length) / 1000)));
+}
+
+fn same_timestamps(new: Option<Timespan>, previous: Option<Timespan>) -> bool {
+    return match (new, previous) {
+        (None, None) => true,
+        (Some((new_start, new_end)), Some((start, end))) => {
+            new_start.abs_diff(start) <= TIMESTAMP_TOLERANCE
+                && new_end.is_some() == end.is_some()
+                && new_end.unwrap_or(0).abs_diff(end.unwrap_or(0)) <= TIMESTAMP_TOLERANCE
+        }
+        _ => false,
+    };
+}
+
+/// Variables that identify the playing track, whichever media source it comes from.
+const TRACK_VARIABLES: [&str; 4] = [
+    "media.source",
+    "media.title",
+    "media.artists",
+    "media.album",
+];
+
+/// Whether the playing track differs between two sets of template variables.
+fn track_changed(previous: &HashMap<String, String>, new: &HashMap<String, String>) -> bool {
+    return TRACK_VARIABLES
+        .iter()
+        .any(|variable: &&str| previous.get(*variable) != new.get(*variable));
+}
+
+/// The `media.*` and `spotify.*` template variables passed to `track_changed` hooks. Progress and play state are left
+/// out since they change without the track changing.
+fn track_variables(template_hashmap: &HashMap<String, String>) -> HashMap<String, String> {
+    return template_hashmap
+        .iter()
+        .filter(|(variable, _)| {
+            (variable.starts_with("media.")
+                && *variable != "media.position"
+                && *variable != "media.status")
+                || (variable.starts_with("spotify.")
+                    && !variable.starts_with("spotify.progress")
+                    && *variable != "spotify.is_playing"
+                    && *variable != "spotify.status"
+                    && !spotify::is_playback_state_variable(variable))
+        })
+        .map(|(variable, value)| (variable.to_owned(), value.to_owned()))
+        .collect();
+}
+
 /// Clears the current Discord activity
 #[instrument(skip_all)]
 pub fn clear_activity(client: &mut DiscordIpcClient) -> Result<(), Box<dyn Error>> {
     return client.clear_activity();
 }
 
-/// Updates `Config` and sets Discord activity if no errors occur during config reread. If an error does occur, a warning will be logged<br/>
-/// but no changes will take place.
-#[instrument(skip_all)]
-pub async fn update_activity(
-    config: &Config,
-    discord: &mut DiscordState,
-    spotify: &Option<AuthCodeSpotify>,
-) -> Result<(), Box<dyn Error>> {
-    // *config = match read_config_file(false) {
-    //     Err(_) => {
-    //         warn!("Config file was not deserialized. Will continue to use old config.");
-    //         return Ok(client);
-    //     }
-    //     Ok(config) => config,
-    // };
-    trace!("Updating Discord activity");
-    return set_activity(config, discord, spotify).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        return pairs
+            .iter()
+            .map(|(variable, value)| (variable.to_string(), value.to_string()))
+            .collect();
+    }
+
+    #[test]
+    fn track_changes_for_any_source() {
+        let playing: HashMap<String, String> = variables(&[
+            ("media.source", "mpd"),
+            ("media.title", "Song"),
+            ("media.artists", "Artist"),
+            ("media.album", "Album"),
+            ("media.position", "0:10"),
+            ("media.status", "playing"),
+        ]);
+
+        let mut later: HashMap<String, String> = playing.to_owned();
+        later.insert(String::from("media.position"), String::from("0:20"));
+        later.insert(String::from("media.status"), String::from("paused"));
+        assert!(!track_changed(&playing, &later));
+
+        for variable in TRACK_VARIABLES {
+            let mut changed: HashMap<String, String> = playing.to_owned();
+            changed.insert(variable.to_owned(), String::from("other"));
+            assert!(track_changed(&playing, &changed), "{variable}");
+        }
+    }
+
+    #[test]
+    fn track_variables_leave_out_progress() {
+        let track: HashMap<String, String> = track_variables(&variables(&[
+            ("media.title", "Song"),
+            ("media.position", "0:10"),
+            ("media.status", "playing"),
+            ("spotify.track.name", "Song"),
+            ("spotify.progress", "0:10"),
+            ("spotify.is_playing", "true"),
+            ("process.name", "code"),
+        ]));
+        assert_eq!(
+            track,
+            variables(&[("media.title", "Song"), ("spotify.track.name", "Song")])
+        );
+    }
+
+    #[test]
+    fn media_timestamps_follow_position() {
+        let mut config: Config = Config::default();
+        config.media.timestamps = true;
+        let media: MediaData = MediaData {
+            status: String::from("playing"),
+            position: 60_000,
+            length: 180_000,
+            ..Default::default()
+        };
+
+        let (start, end) = media_timestamps(&config, &media).unwrap();
+        assert!(crate::task::unix_time().abs_diff(start + 60) <= 1);
+        assert_eq!(end, Some(start + 180));
+
+        let paused: MediaData = MediaData {
+            status: String::from("paused"),
+            ..media.to_owned()
+        };
+        assert_eq!(media_timestamps(&config, &paused), None);
+        config.media.timestamps = false;
+        assert_eq!(media_timestamps(&config, &media), None);
+    }
+
+    #[test]
+    fn same_timestamps_ignore_latency() {
+        assert!(same_timestamps(None, None));
+        assert!(same_timestamps(
+            Some((100, Some(280))),
+            Some((101, Some(281)))
+        ));
+        // A seek moves both timestamps
+        assert!(!same_timestamps(
+            Some((100, Some(280))),
+            Some((130, Some(310)))
+        ));
+        assert!(!same_timestamps(Some((100, Some(280))), Some((100, None))));
+        assert!(!same_timestamps(Some((100, Some(280))), None));
+    }
+
+    #[tokio::test]
+    async fn activity_hook_skips_first_render() {
+        let log: std::path::PathBuf =
+            std::env::temp_dir().join(format!("ddrpc-test-{}-activity", std::process::id()));
+        let _ = std::fs::remove_file(&log);
+
+        let mut config: Config = Config {
+            hooks: vec![HookConfig {
+                command: format!("echo \"$DDRPC_DETAILS\" >> {}", log.display()),
+                discord_connected: false,
+                activity_changed: true,
+                process_exited: None,
+                process_started: None,
+                timeout: 5,
+                track_changed: false,
+            }],
+            ..Config::default()
+        };
+        let mut inputs: Inputs = Inputs::default();
+        let mut discord: DiscordState = DiscordState::new(0);
+
+        config.discord.details = String::from("One");
+        assert!(render_activity(&config, &mut inputs, &mut discord)
+            .await
+            .is_some());
+        config.discord.details = String::from("Two");
+        assert!(render_activity(&config, &mut inputs, &mut discord)
+            .await
+            .is_some());
+
+        let mut written: String = String::new();
+        for _ in 0..50 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            written = std::fs::read_to_string(&log).unwrap_or_default();
+            if !written.is_empty() {
+                break;
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "Two\n", "{written}");
+        std::fs::remove_file(&log).unwrap();
+    }
 }
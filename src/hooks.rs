@@ -0,0 +1,332 @@
+use crate::{credentials::PASSPHRASE_VARIABLE, prelude::*, processes::ProcessData};
+use std::{
+    collections::HashMap,
+    io,
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncReadExt,
+    process::{Child, Command},
+    task::JoinHandle,
+    time::{error::Elapsed, timeout},
+};
+
+/// Something that happened in the presence loop that hooks can be triggered by.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum HookEvent {
+    ProcessStarted(ProcessData),
+    ProcessExited(ProcessData),
+    /// The playing track changed on any media source. Contains the new `media.*` and `spotify.*` variables
+    TrackChanged(HashMap<String, String>),
+    /// The rendered activity changed. Contains the previous and new activity
+    ActivityChanged(DiscordConfig, DiscordConfig),
+    DiscordConnected,
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        return match self {
+            HookEvent::ProcessStarted(_) => "process_started",
+            HookEvent::ProcessExited(_) => "process_exited",
+            HookEvent::TrackChanged(_) => "track_changed",
+            HookEvent::ActivityChanged(_, _) => "activity_changed",
+            HookEvent::DiscordConnected => "discord_connected",
+        };
+    }
+
+    /// Whether `hook` is triggered by this event. Process triggers match the target process name, or any process if set
+    /// to `*`.
+    fn triggers(&self, hook: &HookConfig) -> bool {
+        let matches_process = |trigger: &Option<String>, process: &ProcessData| -> bool {
+            return trigger
+                .as_ref()
+                .is_some_and(|name: &String| name == "*" || *name == process.name);
+        };
+
+        return match self {
+            HookEvent::ProcessStarted(process) => matches_process(&hook.process_started, process),
+            HookEvent::ProcessExited(process) => matches_process(&hook.process_exited, process),
+            HookEvent::TrackChanged(_) => hook.track_changed,
+            HookEvent::ActivityChanged(_, _) => hook.activity_changed,
+            HookEvent::DiscordConnected => hook.discord_connected,
+        };
+    }
+
+    /// Environment variables describing the event, passed to hook commands.
+    fn environment(&self) -> Vec<(String, String)> {
+        let mut environment: Vec<(String, String)> =
+            vec![(String::from("DDRPC_EVENT"), self.name().to_owned())];
+
+        match self {
+            HookEvent::ProcessStarted(process) | HookEvent::ProcessExited(process) => {
+                environment.extend([
+                    (String::from("DDRPC_PROCESS_NAME"), process.name.to_owned()),
+                    (String::from("DDRPC_PROCESS_PID"), process.pid.to_string()),
+                    (String::from("DDRPC_PROCESS_EXE"), process.exe.to_owned()),
+                    (String::from("DDRPC_PROCESS_CWD"), process.cwd.to_owned()),
+                    (
+                        String::from("DDRPC_PROCESS_APP_ID"),
+                        process.app_id.to_owned().unwrap_or_default(),
+                    ),
+                ]);
+            }
+            HookEvent::TrackChanged(variables) => {
+                for (variable, value) in variables {
+                    environment.push((environment_name(variable), value.to_owned()));
+                }
+            }
+            HookEvent::ActivityChanged(previous, new) => {
+                environment.extend([
+                    (String::from("DDRPC_DETAILS"), new.details.to_owned()),
+                    (String::from("DDRPC_STATE"), new.state.to_owned()),
+                    (
+                        String::from("DDRPC_LARGE_IMAGE"),
+                        new.assets.large_image.to_owned(),
+                    ),
+                    (
+                        String::from("DDRPC_SMALL_IMAGE"),
+                        new.assets.small_image.to_owned(),
+                    ),
+                    (
+                        String::from("DDRPC_PREVIOUS_DETAILS"),
+                        previous.details.to_owned(),
+                    ),
+                    (
+                        String::from("DDRPC_PREVIOUS_STATE"),
+                        previous.state.to_owned(),
+                    ),
+                ]);
+            }
+            HookEvent::DiscordConnected => (),
+        }
+
+        return environment;
+    }
+}
+
+/// Converts a template variable name into an environment variable name, e.g. `spotify.track.name` into
+/// `DDRPC_SPOTIFY_TRACK_NAME`.
+fn environment_name(variable: &str) -> String {
+    return format!("DDRPC_{}", variable.replace('.', "_").to_uppercase());
+}
+
+/// Runs every hook triggered by `event`. Each command is started in its own task, so this never waits for a hook to
+/// finish. Returns the tasks, which end when their hook finished or was killed after its timeout.
+#[instrument(skip_all)]
+pub fn run_hooks(hooks: &[HookConfig], event: &HookEvent) -> Vec<JoinHandle<()>> {
+    return hooks
+        .iter()
+        .filter(|hook: &&HookConfig| event.triggers(hook))
+        .map(|hook: &HookConfig| {
+            debug!("Running hook \"{}\" for {}", hook.command, event.name());
+            let command: Command = hook_command(Command::new("sh"), hook, event);
+            return tokio::spawn(run_hook(command, hook.to_owned()));
+        })
+        .collect();
+}
+
+/// Sets up `shell` to run the hook command with the event in its environment. The credentials passphrase is not passed
+/// on.
+fn hook_command(mut shell: Command, hook: &HookConfig, event: &HookEvent) -> Command {
+    shell
+        .arg("-c")
+        .arg(&hook.command)
+        .envs(event.environment())
+        .env_remove(PASSPHRASE_VARIABLE)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    return shell;
+}
+
+/// Runs `command` until it exits, or kills it once `hook.timeout` has passed.
+async fn run_hook(mut command: Command, hook: HookConfig) -> () {
+    let mut child: Child = match command.spawn() {
+        Err(error) => {
+            warn!("Could not run hook \"{}\": {error}", hook.command);
+            return;
+        }
+        Ok(child) => child,
+    };
+
+    let mut stderr: String = String::new();
+    let finished: Result<io::Result<ExitStatus>, Elapsed> =
+        timeout(Duration::from_secs(hook.timeout), async {
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr).await;
+            }
+            return child.wait().await;
+        })
+        .await;
+
+    match finished {
+        Err(_) => {
+            if let Err(error) = child.kill().await {
+                warn!("Could not kill hook \"{}\": {error}", hook.command);
+                return;
+            }
+            warn!(
+                "Hook \"{}\" timed out after {}s and was killed",
+                hook.command, hook.timeout
+            );
+        }
+        Ok(Err(error)) => warn!("Hook \"{}\" failed: {error}", hook.command),
+        Ok(Ok(status)) if !status.success() => warn!(
+            "Hook \"{}\" exited with {status}: {}",
+            hook.command,
+            stderr.trim()
+        ),
+        Ok(Ok(_)) => trace!("Hook \"{}\" finished", hook.command),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    fn hook(command: &str) -> HookConfig {
+        return HookConfig {
+            command: command.to_owned(),
+            discord_connected: false,
+            activity_changed: false,
+            process_exited: None,
+            process_started: None,
+            timeout: 1,
+            track_changed: false,
+        };
+    }
+
+    fn process(name: &str) -> ProcessData {
+        return ProcessData {
+            name: name.to_owned(),
+            app_id: None,
+            pid: 42,
+            start_time: 0,
+            run_time: 0,
+            cpu_usage: 0.0,
+            memory: 0,
+            cwd: String::from("/home/user/project"),
+            exe: String::from("/usr/bin/code"),
+            args: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn triggers_match_event_and_process() {
+        let code: HookConfig = HookConfig {
+            process_started: Some(String::from("code")),
+            ..hook("true")
+        };
+        let any: HookConfig = HookConfig {
+            process_exited: Some(String::from("*")),
+            track_changed: true,
+            ..hook("true")
+        };
+
+        assert!(HookEvent::ProcessStarted(process("code")).triggers(&code));
+        assert!(!HookEvent::ProcessStarted(process("firefox")).triggers(&code));
+        assert!(!HookEvent::ProcessExited(process("code")).triggers(&code));
+        assert!(HookEvent::ProcessExited(process("firefox")).triggers(&any));
+        assert!(!HookEvent::ProcessStarted(process("firefox")).triggers(&any));
+
+        assert!(HookEvent::TrackChanged(HashMap::new()).triggers(&any));
+        assert!(!HookEvent::TrackChanged(HashMap::new()).triggers(&code));
+        assert!(!HookEvent::DiscordConnected.triggers(&any));
+    }
+
+    #[test]
+    fn environment_describes_event() {
+        let environment: Vec<(String, String)> =
+            HookEvent::ProcessStarted(process("code")).environment();
+        for pair in [
+            ("DDRPC_EVENT", "process_started"),
+            ("DDRPC_PROCESS_NAME", "code"),
+            ("DDRPC_PROCESS_PID", "42"),
+            ("DDRPC_PROCESS_CWD", "/home/user/project"),
+            ("DDRPC_PROCESS_APP_ID", ""),
+        ] {
+            assert!(
+                environment.contains(&(pair.0.to_owned(), pair.1.to_owned())),
+                "{pair:?}"
+            );
+        }
+
+        let track: HashMap<String, String> =
+            HashMap::from([(String::from("spotify.track.name"), String::from("Song"))]);
+        assert_eq!(
+            HookEvent::TrackChanged(track).environment(),
+            vec![
+                (String::from("DDRPC_EVENT"), String::from("track_changed")),
+                (
+                    String::from("DDRPC_SPOTIFY_TRACK_NAME"),
+                    String::from("Song")
+                ),
+            ]
+        );
+
+        let previous: DiscordConfig = DiscordConfig {
+            details: String::from("Old"),
+            ..DiscordConfig::new(0)
+        };
+        let new: DiscordConfig = DiscordConfig {
+            details: String::from("New"),
+            ..DiscordConfig::new(0)
+        };
+        let environment: Vec<(String, String)> =
+            HookEvent::ActivityChanged(previous, new).environment();
+        assert!(environment.contains(&(String::from("DDRPC_DETAILS"), String::from("New"))));
+        assert!(
+            environment.contains(&(String::from("DDRPC_PREVIOUS_DETAILS"), String::from("Old")))
+        );
+    }
+
+    #[tokio::test]
+    async fn hooks_do_not_see_the_passphrase() {
+        let output: PathBuf =
+            std::env::temp_dir().join(format!("ddrpc-test-{}-hook-env", std::process::id()));
+        let _ = fs::remove_file(&output);
+
+        let printer: HookConfig = hook(&format!(
+            "echo \"${{{PASSPHRASE_VARIABLE}-unset}} $DDRPC_EVENT\" > {}",
+            output.display()
+        ));
+        // As if the daemon was started with the passphrase in its environment
+        let mut shell: Command = Command::new("sh");
+        shell.env(PASSPHRASE_VARIABLE, "hunter2");
+        let command: Command = hook_command(shell, &printer, &HookEvent::DiscordConnected);
+        run_hook(command, printer).await;
+
+        let written: String = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(written, "unset discord_connected\n");
+    }
+
+    #[tokio::test]
+    async fn hooks_are_killed_after_timeout() {
+        let pid_file: PathBuf =
+            std::env::temp_dir().join(format!("ddrpc-test-{}-hook-pid", std::process::id()));
+        let _ = fs::remove_file(&pid_file);
+
+        let sleeper: HookConfig = HookConfig {
+            discord_connected: true,
+            ..hook(&format!("echo $$ > {}; exec sleep 30", pid_file.display()))
+        };
+        let tasks: Vec<JoinHandle<()>> = run_hooks(&[sleeper], &HookEvent::DiscordConnected);
+        assert_eq!(tasks.len(), 1);
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let pid: String = fs::read_to_string(&pid_file).unwrap();
+        fs::remove_file(&pid_file).unwrap();
+        assert!(
+            !PathBuf::from(format!("/proc/{}", pid.trim())).exists(),
+            "hook process {} still running",
+            pid.trim()
+        );
+    }
+}
@@ -1,32 +1,69 @@
+#![allow(clippy::needless_return, clippy::unused_unit)]
+
 pub mod config;
+pub mod credentials;
+pub mod daemon;
+pub mod desktop;
 pub mod discord;
+pub mod hooks;
+pub mod media;
+pub mod mpd;
+pub mod mpris;
 pub mod parser;
 pub mod processes;
+pub mod runtime;
+pub mod sandbox;
+pub mod schedule;
+pub mod shell;
 pub mod spotify;
+pub mod task;
+pub mod workspace;
 
 pub mod prelude {
     pub use crate::config::*;
     pub use std::error::Error;
     pub use tracing::{debug, error, info, instrument, trace, warn};
 
-    use crate::discord::DiscordState;
-    use rspotify::AuthCodeSpotify;
+    use crate::{
+        daemon::DaemonListener, media::MediaSource, processes::ProcessScanner, schedule::Demand,
+        shell::ShellMonitor, spotify::SpotifyClient,
+    };
+    use discord_rich_presence::DiscordIpcClient;
 
+    /// Everything the presence loop starts from. Handed to [`crate::runtime::run`], which gives each part to its task.
     pub struct AppState {
-        pub discord: DiscordState,
-        pub spotify: Option<AuthCodeSpotify>,
+        /// Socket `ddrpc run` reports tasks on. `None` if it could not be bound
+        pub daemon: Option<DaemonListener>,
+        /// Data sources the config uses
+        pub demand: Demand,
+        /// Connected Discord client
+        pub discord: DiscordIpcClient,
+        /// Sources from `media.order` that could be set up. Empty if the config does not use media
+        pub media: Vec<MediaSource>,
+        pub processes: ProcessScanner,
+        pub shell: ShellMonitor,
+        pub spotify: Option<SpotifyClient>,
         // pub config: Config,
     }
 
     impl AppState {
         pub fn new(
             // config: Config,
-            discord: DiscordState,
-            spotify: Option<AuthCodeSpotify>,
+            daemon: Option<DaemonListener>,
+            demand: Demand,
+            discord: DiscordIpcClient,
+            media: Vec<MediaSource>,
+            shell: ShellMonitor,
+            spotify: Option<SpotifyClient>,
         ) -> Self {
             return Self {
                 // config,
+                daemon,
+                demand,
                 discord,
+                media,
+                processes: ProcessScanner::new(),
+                shell,
                 spotify,
             };
         }
@@ -34,8 +71,10 @@ pub mod prelude {
 }
 
 use clap::Parser;
-use discord::update_activity;
-use parser::{cli::parse_command, Cli};
+use parser::{
+    cli::{parse_command, CommandResult},
+    Cli,
+};
 use prelude::*;
 use std::process::ExitCode;
 use tracing::Level;
@@ -54,27 +93,26 @@ async fn main() -> ExitCode {
         }
         Ok(config) => config,
     };
+    // Secrets in ddrpc.toml still work if they cannot be moved, so this is not fatal
+    if let Err(error) = credentials::migrate(&mut config).await {
+        warn!("Could not move the secrets out of ddrpc.toml: {error}");
+    }
 
     return match parse_command(&mut config, args).await {
         Err(error) => {
             error!("{error}");
             ExitCode::FAILURE
         }
-        Ok(result) => {
-            if let Some(mut app) = result {
-                loop {
-                    std::thread::sleep(std::time::Duration::from_secs(3));
-                    match update_activity(&config, &mut app.discord, &app.spotify).await {
-                        Err(error) => {
-                            error!("{error}");
-                            return ExitCode::FAILURE;
-                        }
-                        _ => (),
-                    }
-                }
+        Ok(CommandResult::Done) => ExitCode::SUCCESS,
+        Ok(CommandResult::Start(app)) => match runtime::run(config, app).await {
+            Err(error) => {
+                error!("{error}");
+                ExitCode::FAILURE
             }
-            ExitCode::SUCCESS
-        }
+            Ok(()) => ExitCode::SUCCESS,
+        },
+        // Exit codes are 0 to 255 on Unix, `run_task` never returns others
+        Ok(CommandResult::Exit(exit_code)) => ExitCode::from(exit_code as u8),
     };
 }
 
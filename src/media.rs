@@ -0,0 +1,232 @@
+use crate::{
+    mpd::MpdProvider,
+    mpris::MprisProvider,
+    prelude::*,
+    processes::format_duration,
+    runtime::{publish, Update},
+    schedule::interval,
+    spotify::{SpotifyClient, SpotifyProvider},
+};
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tokio::{sync::mpsc::UnboundedSender, time::Interval};
+
+/// What a media source is playing, exposed as the `{{media.*}}` variables.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaData {
+    /// Source entry from `media.order` that provided this data, such as `spotify_api` or `mpris:spotify`
+    pub source: String,
+    /// Player name within the source, such as the MPRIS bus name without its prefix
+    pub player: String,
+    pub title: String,
+    pub artists: String,
+    pub album: String,
+    /// Album art URL as given by the source. MPRIS players often give `file://` URLs that Discord cannot show
+    pub art_url: String,
+    pub url: String,
+    /// `playing` or `paused`
+    pub status: String,
+    /// Milliseconds
+    pub position: u64,
+    /// Milliseconds
+    pub length: u64,
+    /// Source specific template variables, like the `spotify.*` variables of the Spotify Web API
+    pub variables: HashMap<String, String>,
+}
+
+impl MediaData {
+    /// Data used when no source is playing anything, taken from `spotify.fallback`.
+    pub fn fallback(config: &SpotifyFallbackConfig) -> Self {
+        return Self {
+            title: config.name.to_owned(),
+            artists: config.artists.to_owned(),
+            album: config.album_name.to_owned(),
+            art_url: config.album_cover_url.to_owned(),
+            url: config.track_url.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    /// `position` as M:SS, or an empty string if the source has no length.
+    pub fn position(&self) -> String {
+        if self.length == 0 {
+            return String::new();
+        }
+        return format_duration(self.position / 1000);
+    }
+
+    /// `length` as M:SS, or an empty string if the source has no length.
+    pub fn length(&self) -> String {
+        if self.length == 0 {
+            return String::new();
+        }
+        return format_duration(self.length / 1000);
+    }
+}
+
+/// A source of currently playing media. Sources are asked in `media.order` and the first one with data wins.
+#[async_trait]
+pub trait MediaProvider: Send + Sync {
+    /// Entry in `media.order` this provider was created from.
+    fn source(&self) -> &str;
+
+    /// What is playing or paused right now. `None` if nothing is, or the source is unavailable.
+    async fn current(&self, config: &Config) -> Option<MediaData>;
+
+    /// Template variables the source sets whether or not it is the chosen one, such as its connection status.
+    fn variables(&self) -> HashMap<String, String> {
+        return HashMap::new();
+    }
+
+    /// Completes when what is playing changed. Only waited for by sources without a refresh interval, which are told
+    /// about changes instead of being asked on every interval.
+    async fn changed(&self) -> () {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// A provider from `media.order` with its refresh interval in seconds, or `None` if it is asked after
+/// [`MediaProvider::changed`] instead.
+pub struct MediaSource {
+    provider: Box<dyn MediaProvider>,
+    interval: Option<u64>,
+}
+
+impl MediaSource {
+    fn new(provider: Box<dyn MediaProvider>, interval: Option<u64>) -> Self {
+        return Self { provider, interval };
+    }
+
+    pub fn source(&self) -> &str {
+        return self.provider.source();
+    }
+
+    /// Asks the provider what is playing on every interval, or every time it changed, and publishes it as
+    /// [`Update::Media`] when it changed. `index` is the position of the source among all sources. Runs until the
+    /// renderer stops.
+    pub async fn run(
+        self,
+        config: Arc<Config>,
+        index: usize,
+        updates: UnboundedSender<Update>,
+    ) -> () {
+        let mut interval: Option<Interval> = self.interval.map(interval);
+        let mut last: Option<(Option<MediaData>, HashMap<String, String>)> = None;
+        loop {
+            match interval.as_mut() {
+                Some(interval) => {
+                    interval.tick().await;
+                }
+                None => self.provider.changed().await,
+            }
+            let media: Option<MediaData> = self.provider.current(&config).await;
+            let update =
+                |(media, variables): (Option<MediaData>, HashMap<String, String>)| Update::Media {
+                    index,
+                    media,
+                    variables,
+                };
+            if !publish(
+                &updates,
+                &mut last,
+                (media, self.provider.variables()),
+                update,
+            ) {
+                return;
+            }
+        }
+    }
+}
+
+/// What a source from `media.order` published last, kept by the renderer.
+#[derive(Clone, Debug, Default)]
+pub struct MediaState {
+    pub source: String,
+    /// Data from the last update and when it was received
+    pub last: Option<(MediaData, Instant)>,
+    /// Variables the source sets whether or not it is the chosen one
+    pub variables: HashMap<String, String>,
+}
+
+impl MediaState {
+    pub fn new(source: &str) -> Self {
+        return Self {
+            source: source.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    /// Data from the last update, with the position moved forward by the time since then if it is playing.
+    fn current(&self) -> Option<MediaData> {
+        let (media, received) = self.last.as_ref()?;
+        let mut media: MediaData = media.to_owned();
+        if media.status == "playing" {
+            media.position += received.elapsed().as_millis() as u64;
+            if media.length > 0 {
+                media.position = media.position.min(media.length);
+            }
+        }
+        return Some(media);
+    }
+}
+
+/// Creates the providers listed in `media.order`. Entries whose source is not set up, such as `spotify_api` without a
+/// Spotify app, are skipped.
+#[instrument(skip_all)]
+pub async fn media_providers(config: &Config, spotify: Option<&SpotifyClient>) -> Vec<MediaSource> {
+    let mut providers: Vec<MediaSource> = Vec::new();
+
+    for source in &config.media.order {
+        let (kind, argument) = match source.split_once(':') {
+            None => (source.as_str(), None),
+            Some((kind, argument)) => (kind, Some(argument.to_owned())),
+        };
+
+        match kind {
+            "mpd" => providers.push(MediaSource::new(
+                Box::new(MpdProvider::new(&config.mpd)),
+                None,
+            )),
+            "mpris" => match MprisProvider::new(&config.mpris, source, argument).await {
+                None => debug!("Skipping media source {source}"),
+                Some(provider) => providers.push(MediaSource::new(
+                    Box::new(provider),
+                    Some(config.mpris.interval),
+                )),
+            },
+            "spotify_api" => match spotify {
+                None => debug!("Skipping media source {source}, Spotify is not authorized"),
+                Some(client) => providers.push(MediaSource::new(
+                    Box::new(SpotifyProvider::new(client.to_owned(), config)),
+                    Some(config.spotify.interval),
+                )),
+            },
+            _ => warn!("Unknown media source \"{source}\" in media.order"),
+        }
+    }
+
+    return providers;
+}
+
+/// Returns the data of the first source in order that is playing something. Returns `None` if no source is playing
+/// anything.
+#[instrument(skip_all)]
+pub fn current_media(sources: &[MediaState]) -> Option<MediaData> {
+    for source in sources {
+        if let Some(mut media) = source.current() {
+            trace!("Media from {}: {media:?}", source.source);
+            media.source = source.source.to_owned();
+            return Some(media);
+        }
+    }
+    trace!("No media source is playing anything");
+    return None;
+}
+
+/// Variables every provider sets regardless of which one is chosen, like `{{spotify.status}}`.
+pub fn provider_variables(sources: &[MediaState]) -> HashMap<String, String> {
+    return sources
+        .iter()
+        .flat_map(|source: &MediaState| source.variables.to_owned())
+        .collect();
+}
@@ -0,0 +1,435 @@
+use crate::{
+    media::{MediaData, MediaProvider},
+    prelude::*,
+};
+use async_trait::async_trait;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+    sync::Notify,
+    task::JoinHandle,
+    time::sleep,
+};
+
+/// Time to wait before reconnecting after the connection to MPD failed or was closed
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Music Player Daemon, as the `mpd` entry in `media.order`. A background task keeps a connection open and waits for
+/// player changes with `idle player`, so MPD is only read when its player changed.
+pub struct MpdProvider {
+    state: Arc<Mutex<Option<MpdState>>>,
+    /// Notified by the background task every time `state` changed
+    changed: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+/// Player state from the last change, with the time it was read to advance the position while playing.
+struct MpdState {
+    media: MediaData,
+    updated: Instant,
+}
+
+impl MpdProvider {
+    pub fn new(config: &MpdConfig) -> Self {
+        let state: Arc<Mutex<Option<MpdState>>> = Arc::new(Mutex::new(None));
+        let changed: Arc<Notify> = Arc::new(Notify::new());
+        let config: MpdConfig = config.to_owned();
+        let task_state: Arc<Mutex<Option<MpdState>>> = Arc::clone(&state);
+        let task_changed: Arc<Notify> = Arc::clone(&changed);
+
+        let task: JoinHandle<()> = tokio::spawn(async move {
+            loop {
+                if let Err(error) = watch_player(&config, &task_state, &task_changed).await {
+                    debug!("MPD connection to {} closed: {error}", config.address);
+                }
+                *task_state.lock().expect("MPD state mutex poisoned") = None;
+                task_changed.notify_one();
+                sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        return Self {
+            state,
+            changed,
+            task,
+        };
+    }
+}
+
+impl Drop for MpdProvider {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl MediaProvider for MpdProvider {
+    fn source(&self) -> &str {
+        return "mpd";
+    }
+
+    async fn current(&self, _config: &Config) -> Option<MediaData> {
+        let state = self.state.lock().expect("MPD state mutex poisoned");
+        let state: &MpdState = state.as_ref()?;
+
+        let mut media: MediaData = state.media.to_owned();
+        if media.status == "playing" {
+            media.position += state.updated.elapsed().as_millis() as u64;
+            if media.length > 0 {
+                media.position = media.position.min(media.length);
+            }
+        }
+        return Some(media);
+    }
+
+    async fn changed(&self) -> () {
+        self.changed.notified().await;
+    }
+}
+
+trait MpdStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MpdStream for T {}
+
+/// Connection speaking the MPD text protocol. Responses are `key: value` lines ending with `OK`, or an `ACK` line on
+/// errors.
+struct MpdConnection {
+    stream: BufReader<Box<dyn MpdStream>>,
+}
+
+impl MpdConnection {
+    /// Connects to a Unix socket if `address` is a path, otherwise to `host:port` over TCP.
+    async fn connect(address: &str) -> Result<Self, Box<dyn Error>> {
+        let stream: Box<dyn MpdStream> = if address.starts_with('/') || address.starts_with('~') {
+            let path: String = match (address.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(path), Some(home)) => home.join(path).to_string_lossy().into_owned(),
+                _ => address.to_owned(),
+            };
+            Box::new(UnixStream::connect(path).await?)
+        } else {
+            Box::new(TcpStream::connect(address).await?)
+        };
+
+        let mut connection: Self = Self {
+            stream: BufReader::new(stream),
+        };
+        let greeting: String = connection.read_line().await?;
+        if !greeting.starts_with("OK MPD ") {
+            return Err(format!("Unexpected MPD greeting \"{greeting}\"").into());
+        }
+        trace!("Connected to {}", greeting.trim_start_matches("OK "));
+
+        return Ok(connection);
+    }
+
+    async fn read_line(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut line: String = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err("Connection closed".into());
+        }
+        return Ok(line.trim_end_matches('\n').to_owned());
+    }
+
+    /// Sends a command and returns the `key: value` pairs of the response.
+    async fn command(&mut self, command: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{command}\n").as_bytes())
+            .await?;
+
+        let mut pairs: Vec<(String, String)> = Vec::new();
+        loop {
+            let line: String = self.read_line().await?;
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if line.starts_with("ACK ") {
+                return Err(line.into());
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+}
+
+/// Reads the player state every time it changes until the connection fails, notifying `changed` after each read.
+async fn watch_player(
+    config: &MpdConfig,
+    state: &Mutex<Option<MpdState>>,
+    changed: &Notify,
+) -> Result<(), Box<dyn Error>> {
+    let mut connection: MpdConnection = MpdConnection::connect(&config.address).await?;
+    if !config.password.is_empty() {
+        connection
+            .command(&format!("password {}", quote(&config.password)))
+            .await?;
+    }
+    debug!("Watching MPD at {}", config.address);
+
+    loop {
+        let status: Vec<(String, String)> = connection.command("status").await?;
+        let song: Vec<(String, String)> = connection.command("currentsong").await?;
+        let media: Option<MediaData> = media_data(&status, &song);
+        trace!("MPD player changed: {media:?}");
+
+        *state.lock().expect("MPD state mutex poisoned") = media.map(|media: MediaData| MpdState {
+            media,
+            updated: Instant::now(),
+        });
+        changed.notify_one();
+
+        connection.command("idle player").await?;
+    }
+}
+
+/// Converts the responses of `status` and `currentsong`. Returns `None` if playback is stopped.
+fn media_data(status: &[(String, String)], song: &[(String, String)]) -> Option<MediaData> {
+    let get = |pairs: &[(String, String)], key: &str| -> Option<String> {
+        return pairs
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.to_owned());
+    };
+    let seconds = |value: Option<String>| -> u64 {
+        return value
+            .and_then(|value: String| value.parse::<f64>().ok())
+            .map_or(0, |seconds: f64| (seconds * 1000.0) as u64);
+    };
+
+    let status_text: &str = match get(status, "state")?.as_str() {
+        "play" => "playing",
+        "pause" => "paused",
+        _ => return None,
+    };
+
+    let file: String = get(song, "file").unwrap_or_default();
+    // Older servers only have `time` as `elapsed:duration` in whole seconds
+    let time: Option<String> = get(status, "time");
+    let duration: Option<String> = get(status, "duration").or_else(|| {
+        time.as_ref()
+            .and_then(|time: &String| time.split_once(':'))
+            .map(|(_, duration)| duration.to_owned())
+    });
+
+    return Some(MediaData {
+        player: String::from("mpd"),
+        // Streams often only have a station `Name`, and untagged files only have a path
+        title: get(song, "Title")
+            .or_else(|| get(song, "Name"))
+            .unwrap_or_else(|| file.rsplit('/').next().unwrap_or_default().to_owned()),
+        artists: song
+            .iter()
+            .filter(|(key, _)| key == "Artist")
+            .map(|(_, artist)| artist.as_str())
+            .collect::<Vec<&str>>()
+            .join(", "),
+        album: get(song, "Album").unwrap_or_default(),
+        url: match file.contains("://") {
+            true => file.to_owned(),
+            false => String::new(),
+        },
+        status: String::from(status_text),
+        position: seconds(get(status, "elapsed").or_else(|| {
+            time.as_ref()
+                .and_then(|time: &String| time.split_once(':'))
+                .map(|(elapsed, _)| elapsed.to_owned())
+        })),
+        length: seconds(duration),
+        ..Default::default()
+    });
+}
+
+/// Quotes a command argument, escaping backslashes and double quotes.
+fn quote(argument: &str) -> String {
+    return format!(
+        "\"{}\"",
+        argument.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        time::timeout,
+    };
+
+    fn pairs(response: &str) -> Vec<(String, String)> {
+        return response
+            .lines()
+            .filter_map(|line: &str| line.split_once(": "))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+    }
+
+    #[test]
+    fn media_data_from_responses() {
+        let media: MediaData = media_data(
+            &pairs("volume: 50\nstate: play\ntime: 12:180\nelapsed: 12.5\nduration: 180.2"),
+            &pairs("file: music/a.flac\nArtist: X\nArtist: Y\nAlbum: Al\nTitle: T"),
+        )
+        .unwrap();
+        assert_eq!(media.player, "mpd");
+        assert_eq!(media.title, "T");
+        assert_eq!(media.artists, "X, Y");
+        assert_eq!(media.album, "Al");
+        assert_eq!(media.url, "");
+        assert_eq!(media.status, "playing");
+        assert_eq!(media.position, 12_500);
+        assert_eq!(media.length, 180_200);
+    }
+
+    #[test]
+    fn media_data_fallbacks() {
+        // Older servers only send `time`
+        let media: MediaData = media_data(
+            &pairs("state: pause\ntime: 61:240"),
+            &pairs("file: albums/Some Artist/01 Untagged.ogg"),
+        )
+        .unwrap();
+        assert_eq!(media.title, "01 Untagged.ogg");
+        assert_eq!(media.status, "paused");
+        assert_eq!(media.position, 61_000);
+        assert_eq!(media.length, 240_000);
+
+        let stream: MediaData = media_data(
+            &pairs("state: play\nelapsed: 3.000"),
+            &pairs("file: https://radio.example.com/stream\nName: Example Radio"),
+        )
+        .unwrap();
+        assert_eq!(stream.title, "Example Radio");
+        assert_eq!(stream.url, "https://radio.example.com/stream");
+        assert_eq!(stream.length, 0);
+
+        assert_eq!(media_data(&pairs("state: stop"), &Vec::new()), None);
+        assert_eq!(media_data(&pairs("volume: 50"), &Vec::new()), None);
+    }
+
+    #[test]
+    fn quote_arguments() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote(r#"se"cr\et"#), r#""se\"cr\\et""#);
+    }
+
+    /// Answers one connection like MPD. The first `idle player` reports a change to the next song, which is paused,
+    /// and later ones never return. Received commands are sent to `commands`.
+    async fn serve_mpd(
+        listener: TcpListener,
+        commands: tokio::sync::mpsc::UnboundedSender<String>,
+    ) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"OK MPD 0.23.5\n").await.unwrap();
+
+        let mut song: u32 = 0;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let _ = commands.send(line.to_owned());
+            let response: String = match line.as_str() {
+                "password \"se\\\"cret\"" => String::from("OK\n"),
+                line if line.starts_with("password ") => {
+                    String::from("ACK [3@0] {password} incorrect password\n")
+                }
+                "status" => format!(
+                    "volume: 50\nstate: {}\nelapsed: 12.500\nduration: 180.200\nOK\n",
+                    if song == 0 { "play" } else { "pause" }
+                ),
+                "currentsong" => {
+                    format!("file: music/{song}.flac\nArtist: X\nTitle: T{song}\nOK\n")
+                }
+                "idle player" if song == 0 => {
+                    song += 1;
+                    String::from("changed: player\nOK\n")
+                }
+                "idle player" => std::future::pending::<String>().await,
+                _ => String::from("ACK [5@0] {} unknown command\n"),
+            };
+            writer.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn start_stub() -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address: String = listener.local_addr().unwrap().to_string();
+        let (commands, received) = tokio::sync::mpsc::unbounded_channel::<String>();
+        tokio::spawn(serve_mpd(listener, commands));
+        return (address, received);
+    }
+
+    #[tokio::test]
+    async fn watch_player_follows_changes() {
+        let (address, mut received) = start_stub().await;
+        let config: MpdConfig = MpdConfig {
+            address,
+            password: String::from("se\"cret"),
+        };
+        let state: Arc<Mutex<Option<MpdState>>> = Arc::new(Mutex::new(None));
+        let changed: Arc<Notify> = Arc::new(Notify::new());
+        let task_state: Arc<Mutex<Option<MpdState>>> = Arc::clone(&state);
+        let task_changed: Arc<Notify> = Arc::clone(&changed);
+        let watcher: JoinHandle<()> = tokio::spawn(async move {
+            let _ = watch_player(&config, &task_state, &task_changed).await;
+        });
+
+        let mut commands: Vec<String> = Vec::new();
+        while commands
+            .iter()
+            .filter(|command| *command == "idle player")
+            .count()
+            < 2
+        {
+            let command: Option<String> = timeout(Duration::from_secs(5), received.recv())
+                .await
+                .unwrap();
+            commands.push(command.unwrap());
+        }
+        assert_eq!(
+            commands,
+            [
+                "password \"se\\\"cret\"",
+                "status",
+                "currentsong",
+                "idle player",
+                "status",
+                "currentsong",
+                "idle player",
+            ]
+        );
+
+        // The change was announced, so the media source does not have to poll for it
+        timeout(Duration::from_secs(5), changed.notified())
+            .await
+            .unwrap();
+        let media: MediaData = state.lock().unwrap().as_ref().unwrap().media.to_owned();
+        assert_eq!(media.title, "T1");
+        assert_eq!(media.status, "paused");
+        assert_eq!(media.position, 12_500);
+        watcher.abort();
+    }
+
+    #[tokio::test]
+    async fn watch_player_fails_with_wrong_password() {
+        let (address, _received) = start_stub().await;
+        let config: MpdConfig = MpdConfig {
+            address,
+            password: String::from("wrong"),
+        };
+        let state: Mutex<Option<MpdState>> = Mutex::new(None);
+
+        let error: Box<dyn Error> = timeout(
+            Duration::from_secs(5),
+            watch_player(&config, &state, &Notify::new()),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert!(error.to_string().contains("incorrect password"), "{error}");
+        assert!(state.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{
+    media::{MediaData, MediaProvider},
+    prelude::*,
+};
+use async_trait::async_trait;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::timeout;
+use zbus::{
+    fdo::{DBusProxy, PropertiesProxy},
+    names::InterfaceName,
+    zvariant::OwnedValue,
+    Connection,
+};
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+/// A player that does not answer in time is skipped instead of delaying the activity update
+const PLAYER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// MPRIS players on the session bus. Created from a `mpris` entry in `media.order`, or `mpris:<player>` to only read one
+/// player.
+pub struct MprisProvider {
+    connection: Connection,
+    source: String,
+    player: Option<String>,
+}
+
+impl MprisProvider {
+    /// Connects to the session bus. Returns `None` if MPRIS is disabled or there is no session bus.
+    #[instrument(skip_all)]
+    pub async fn new(config: &MprisConfig, source: &str, player: Option<String>) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        return match Connection::session().await {
+            Err(error) => {
+                warn!("Could not connect to the session D-Bus, MPRIS players will not be read: {error}");
+                None
+            }
+            Ok(connection) => {
+                debug!("Connected to the session D-Bus");
+                Some(Self {
+                    connection,
+                    source: source.to_owned(),
+                    player,
+                })
+            }
+        };
+    }
+
+    /// Data of the player to show, chosen with [`best_player`] from the players [`player_wanted`] allows.
+    #[instrument(skip_all)]
+    async fn choose_player(&self, config: &MprisConfig) -> Option<MediaData> {
+        let names = match DBusProxy::new(&self.connection).await {
+            Err(error) => {
+                warn!("Could not list D-Bus names: {error}");
+                return None;
+            }
+            Ok(proxy) => proxy.list_names().await.unwrap_or_default(),
+        };
+
+        let mut players: Vec<MediaData> = Vec::new();
+        for name in names {
+            let Some(player) = name.as_str().strip_prefix(BUS_NAME_PREFIX) else {
+                continue;
+            };
+            if !player_wanted(player, self.player.as_deref(), config) {
+                continue;
+            }
+
+            match timeout(PLAYER_TIMEOUT, self.player_data(name.as_str())).await {
+                Err(_) => debug!("MPRIS player {player} did not answer in time"),
+                Ok(Err(error)) => debug!("Could not read MPRIS player {player}: {error}"),
+                Ok(Ok(data)) => players.push(data),
+            }
+        }
+
+        let chosen: Option<MediaData> = best_player(players, config);
+        trace!("Chosen MPRIS player: {chosen:?}");
+        return chosen;
+    }
+
+    async fn player_data(&self, bus_name: &str) -> Result<MediaData, Box<dyn Error>> {
+        let properties: HashMap<String, OwnedValue> = PropertiesProxy::builder(&self.connection)
+            .destination(bus_name.to_owned())?
+            .path(OBJECT_PATH)?
+            .build()
+            .await?
+            .get_all(Some(InterfaceName::from_static_str(PLAYER_INTERFACE)?).into())
+            .await?;
+
+        return media_data(bus_name, &properties);
+    }
+}
+
+#[async_trait]
+impl MediaProvider for MprisProvider {
+    fn source(&self) -> &str {
+        return &self.source;
+    }
+
+    async fn current(&self, config: &Config) -> Option<MediaData> {
+        return self.choose_player(&config.mpris).await;
+    }
+}
+
+/// Maps the properties of the MPRIS player interface to [`MediaData`].
+fn media_data(
+    bus_name: &str,
+    properties: &HashMap<String, OwnedValue>,
+) -> Result<MediaData, Box<dyn Error>> {
+    let metadata: HashMap<String, OwnedValue> = match properties.get("Metadata") {
+        None => HashMap::new(),
+        Some(metadata) => HashMap::try_from(metadata.try_clone()?)?,
+    };
+
+    return Ok(MediaData {
+        player: bus_name
+            .strip_prefix(BUS_NAME_PREFIX)
+            .unwrap_or(bus_name)
+            .to_owned(),
+        title: string_value(metadata.get("xesam:title")),
+        artists: string_value(metadata.get("xesam:artist")),
+        album: string_value(metadata.get("xesam:album")),
+        art_url: string_value(metadata.get("mpris:artUrl")),
+        url: string_value(metadata.get("xesam:url")),
+        status: string_value(properties.get("PlaybackStatus")).to_lowercase(),
+        // MPRIS times are in microseconds
+        position: integer_value(properties.get("Position")) / 1000,
+        length: integer_value(metadata.get("mpris:length")) / 1000,
+        ..Default::default()
+    });
+}
+
+/// Whether a player should be read: it is the only player the source reads if there is one, and it is not in
+/// `MprisConfig::ignore`.
+fn player_wanted(player: &str, only: Option<&str>, config: &MprisConfig) -> bool {
+    if only.is_some_and(|only: &str| !player_matches(player, only)) {
+        return false;
+    }
+    if config
+        .ignore
+        .iter()
+        .any(|ignored: &String| player_matches(player, ignored))
+    {
+        trace!("Ignoring MPRIS player {player}");
+        return false;
+    }
+    return true;
+}
+
+/// Chooses the player to show. Playing players come before paused ones, then players are ordered by
+/// `MprisConfig::players`, with unlisted players after listed ones. Stopped players are never chosen.
+fn best_player(mut players: Vec<MediaData>, config: &MprisConfig) -> Option<MediaData> {
+    players.retain(|data: &MediaData| {
+        if data.status == "stopped" {
+            trace!("MPRIS player {} is stopped", data.player);
+        }
+        return data.status != "stopped";
+    });
+    players.sort_by_key(|data: &MediaData| {
+        (
+            data.status != "playing",
+            config
+                .players
+                .iter()
+                .position(|listed: &String| player_matches(&data.player, listed))
+                .unwrap_or(usize::MAX),
+        )
+    });
+    return players.into_iter().next();
+}
+
+/// Whether `player` is `name` or an instance of it, like `firefox.instance_1_42` for `firefox`.
+fn player_matches(player: &str, name: &str) -> bool {
+    return player == name
+        || player
+            .strip_prefix(name)
+            .is_some_and(|instance: &str| instance.starts_with('.'));
+}
+
+/// Reads a string, or a list of strings joined with `, `. Some players send single artists as a plain string.
+fn string_value(value: Option<&OwnedValue>) -> String {
+    let Some(value) = value else {
+        return String::new();
+    };
+    if let Ok(string) = <&str>::try_from(value) {
+        return string.to_owned();
+    }
+    return value
+        .try_clone()
+        .ok()
+        .and_then(|value: OwnedValue| Vec::<String>::try_from(value).ok())
+        .map(|strings: Vec<String>| strings.join(", "))
+        .unwrap_or_default();
+}
+
+/// Reads a length or position. The spec uses signed 64-bit integers, but some players send unsigned ones.
+fn integer_value(value: Option<&OwnedValue>) -> u64 {
+    let Some(value) = value else {
+        return 0;
+    };
+    return i64::try_from(value)
+        .ok()
+        .and_then(|value: i64| u64::try_from(value).ok())
+        .or_else(|| u64::try_from(value).ok())
+        .unwrap_or(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+    use zbus::zvariant::Value;
+
+    fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+        return OwnedValue::try_from(value.into()).unwrap();
+    }
+
+    fn metadata(title: &str, artists: Vec<&str>) -> HashMap<String, OwnedValue> {
+        return HashMap::from([
+            (String::from("xesam:title"), owned(title)),
+            (String::from("xesam:artist"), owned(artists)),
+            (String::from("xesam:album"), owned("Album")),
+            (String::from("mpris:artUrl"), owned("file:///tmp/cover.png")),
+            (
+                String::from("xesam:url"),
+                owned("https://example.com/track"),
+            ),
+            (String::from("mpris:length"), owned(215_000_000i64)),
+        ]);
+    }
+
+    fn properties(
+        status: &str,
+        metadata: HashMap<String, OwnedValue>,
+    ) -> HashMap<String, OwnedValue> {
+        return HashMap::from([
+            (String::from("PlaybackStatus"), owned(status)),
+            (String::from("Position"), owned(42_500_000i64)),
+            (String::from("Metadata"), owned(metadata)),
+        ]);
+    }
+
+    fn player(player: &str, status: &str) -> MediaData {
+        return MediaData {
+            player: player.to_owned(),
+            status: status.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn media_data_from_properties() {
+        let data: MediaData = media_data(
+            "org.mpris.MediaPlayer2.spotify",
+            &properties("Playing", metadata("Song", vec!["One", "Two"])),
+        )
+        .unwrap();
+
+        assert_eq!(data.player, "spotify");
+        assert_eq!(data.title, "Song");
+        assert_eq!(data.artists, "One, Two");
+        assert_eq!(data.album, "Album");
+        assert_eq!(data.art_url, "file:///tmp/cover.png");
+        assert_eq!(data.url, "https://example.com/track");
+        assert_eq!(data.status, "playing");
+        assert_eq!(data.position, 42_500);
+        assert_eq!(data.length, 215_000);
+    }
+
+    #[test]
+    fn media_data_with_plain_values() {
+        let metadata: HashMap<String, OwnedValue> = HashMap::from([
+            (String::from("xesam:artist"), owned("Single Artist")),
+            (String::from("mpris:length"), owned(3_000_000u64)),
+        ]);
+        let data: MediaData = media_data(
+            "org.mpris.MediaPlayer2.mpv",
+            &properties("Paused", metadata),
+        )
+        .unwrap();
+        assert_eq!(data.artists, "Single Artist");
+        assert_eq!(data.length, 3_000);
+        assert_eq!(data.title, "");
+
+        let data: MediaData = media_data("org.mpris.MediaPlayer2.vlc", &HashMap::new()).unwrap();
+        assert_eq!(data.player, "vlc");
+        assert_eq!(data.status, "");
+        assert_eq!(data.position, 0);
+    }
+
+    #[test]
+    fn player_selection() {
+        let config: MprisConfig = MprisConfig {
+            ignore: vec![String::from("firefox")],
+            players: vec![String::from("spotify"), String::from("mpv")],
+            ..MprisConfig::default()
+        };
+
+        assert!(player_wanted("spotify", None, &config));
+        assert!(!player_wanted("firefox", None, &config));
+        assert!(!player_wanted("firefox.instance_1_42", None, &config));
+        assert!(player_wanted("firefoxish", None, &config));
+        assert!(player_wanted(
+            "chromium.instance2",
+            Some("chromium"),
+            &config
+        ));
+        assert!(!player_wanted("spotify", Some("chromium"), &config));
+
+        let chosen = |players: Vec<MediaData>| {
+            best_player(players, &config).map(|data: MediaData| data.player)
+        };
+        // Playing wins over priority
+        assert_eq!(
+            chosen(vec![player("spotify", "paused"), player("vlc", "playing")]).as_deref(),
+            Some("vlc")
+        );
+        // Then listed players in order, then unlisted ones
+        assert_eq!(
+            chosen(vec![
+                player("vlc", "playing"),
+                player("mpv.instance7", "playing"),
+                player("spotify", "playing"),
+            ])
+            .as_deref(),
+            Some("spotify")
+        );
+        assert_eq!(
+            chosen(vec![player("vlc", "paused"), player("mpv", "paused")]).as_deref(),
+            Some("mpv")
+        );
+        assert_eq!(chosen(vec![player("spotify", "stopped")]), None);
+        assert_eq!(chosen(Vec::new()), None);
+    }
+
+    struct FakePlayer {
+        status: String,
+        metadata: HashMap<String, OwnedValue>,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            return self.status.to_owned();
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            return self
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.try_clone().unwrap()))
+                .collect();
+        }
+
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            return 1_000_000;
+        }
+    }
+
+    /// Private session bus, stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon: Child = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon is needed for this test");
+            let mut address: String = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            return Self {
+                daemon,
+                address: address.trim().to_owned(),
+            };
+        }
+
+        async fn serve(&self, name: &str, status: &str, title: &str) -> Connection {
+            let player: FakePlayer = FakePlayer {
+                status: status.to_owned(),
+                metadata: metadata(title, vec!["Artist"]),
+            };
+            return zbus::connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .name(format!("{BUS_NAME_PREFIX}{name}"))
+                .unwrap()
+                .serve_at(OBJECT_PATH, player)
+                .unwrap()
+                .build()
+                .await
+                .unwrap();
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "starts a dbus-daemon"]
+    async fn choose_player_on_session_bus() {
+        let bus: Bus = Bus::start();
+        let _players: Vec<Connection> = vec![
+            bus.serve("vlc", "Playing", "Unlisted").await,
+            bus.serve("spotify", "Paused", "Paused song").await,
+            bus.serve("mpv.instance1", "Playing", "Listed").await,
+            bus.serve("firefox", "Playing", "Ignored").await,
+            bus.serve("rhythmbox", "Stopped", "Stopped").await,
+        ];
+        let connection: Connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let config: MprisConfig = MprisConfig {
+            ignore: vec![String::from("firefox")],
+            players: vec![String::from("spotify"), String::from("mpv")],
+            ..MprisConfig::default()
+        };
+
+        let provider: MprisProvider = MprisProvider {
+            connection: connection.to_owned(),
+            source: String::from("mpris"),
+            player: None,
+        };
+        let data: MediaData = provider.choose_player(&config).await.unwrap();
+        assert_eq!(data.player, "mpv.instance1");
+        assert_eq!(data.title, "Listed");
+        assert_eq!(data.artists, "Artist");
+        assert_eq!(data.position, 1_000);
+
+        let provider: MprisProvider = MprisProvider {
+            connection: connection.to_owned(),
+            source: String::from("mpris:spotify"),
+            player: Some(String::from("spotify")),
+        };
+        let data: MediaData = provider.choose_player(&config).await.unwrap();
+        assert_eq!(data.title, "Paused song");
+        assert_eq!(data.status, "paused");
+
+        let provider: MprisProvider = MprisProvider {
+            connection,
+            source: String::from("mpris:rhythmbox"),
+            player: Some(String::from("rhythmbox")),
+        };
+        assert_eq!(provider.choose_player(&config).await, None);
+    }
+}
@@ -1,28 +1,47 @@
-use crate::{discord::*, parser::*, prelude::*, processes::*};
+use crate::{
+    credentials,
+    discord::*,
+    parser::*,
+    prelude::*,
+    processes::*,
+    shell::print_shell_init,
+    spotify::{print_spotify_status, set_spotify_client, spotify_login, spotify_logout},
+    task::run_task,
+};
+
+/// What `main` does once a command has been handled.
+#[allow(clippy::large_enum_variant)]
+pub enum CommandResult {
+    /// Exit successfully
+    Done,
+    /// Run the presence loop
+    Start(AppState),
+    /// Exit with the exit code of the command `ddrpc run` ran
+    Exit(i32),
+}
 
 /// Parse CLI subcommands and flags and call their respective functions.
 #[instrument(skip_all)]
 pub async fn parse_command(
     config: &mut Config,
     args: Cli,
-) -> Result<Option<AppState>, Box<dyn Error>> {
+) -> Result<CommandResult, Box<dyn Error>> {
     trace!("Parsing command: {args:?}");
 
     return match args.subcommands {
         CliSubcommands::Discord(arg) => match arg.subcommands {
-            CliDiscordSubcommands::Connect => {
-                let mut app: AppState = client_init(config).await?;
-                set_activity(config, &mut app.discord, &app.spotify).await?;
-                Ok(Some(app))
+            CliDiscordSubcommands::Connect(arg) => {
+                let app: AppState = client_init(config, arg.no_browser).await?;
+                Ok(CommandResult::Start(app))
             }
             CliDiscordSubcommands::Disconnect => unimplemented!(),
             CliDiscordSubcommands::Get(_arg) => {
                 print_activity_data(&config.discord);
-                Ok(None)
+                Ok(CommandResult::Done)
             }
             CliDiscordSubcommands::Set(args) => {
                 set_activity_data(&mut config.discord, args)?;
-                Ok(None)
+                Ok(CommandResult::Done)
             }
             CliDiscordSubcommands::Update => unimplemented!(),
         },
@@ -30,40 +49,87 @@ pub async fn parse_command(
         CliSubcommands::Processes(arg) => match arg.subcommands {
             CliProcessesSubcommands::Add(arg) => {
                 add_process(&mut config.processes, arg)?;
-                Ok(None)
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Dedupe(arg) => {
+                dedupe_processes(&mut config.processes, arg.fix)?;
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Discover(arg) => {
+                discover_processes(&mut config.processes, arg)?;
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Edit(arg) => {
+                edit_process(&mut config.processes, arg)?;
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Export(arg) => {
+                export_processes(&config.processes, arg)?;
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Import(arg) => {
+                import_processes(&mut config.processes, arg)?;
+                Ok(CommandResult::Done)
             }
             CliProcessesSubcommands::List => {
                 print_data_list(&config.processes);
-                Ok(None)
+                Ok(CommandResult::Done)
             }
             CliProcessesSubcommands::Priority(arg) => {
                 change_process_priority(&mut config.processes, arg)?;
-                Ok(None)
+                Ok(CommandResult::Done)
             }
             CliProcessesSubcommands::Remove(arg) => {
                 remove_process(&mut config.processes, arg.name)?;
-                Ok(None)
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Strategy(arg) => {
+                set_process_strategy(&mut config.processes, arg.strategy)?;
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Show(arg) => {
+                show_processes(&config.processes, arg);
+                Ok(CommandResult::Done)
+            }
+            CliProcessesSubcommands::Test(arg) => {
+                test_process(&config.processes, arg);
+                Ok(CommandResult::Done)
             }
-            CliProcessesSubcommands::Show => todo!(),
         },
         CliSubcommands::Ping => {
             println!("pong");
-            Ok(None)
+            Ok(CommandResult::Done)
         }
         CliSubcommands::Refresh => unimplemented!(),
-        CliSubcommands::Spotify(arg) => match arg.subcommands {
-            CliSpotifySubcommands::Add => todo!(),
-            CliSpotifySubcommands::Client(arg) => {
-                if arg.id.is_some() {
-                    todo!()
-                };
-                if arg.secret.is_some() {
-                    todo!()
-                };
-                unimplemented!();
-            }
-            CliSpotifySubcommands::Remove => todo!(),
-        },
+        CliSubcommands::Run(arg) => {
+            let exit_code: i32 = run_task(arg).await?;
+            Ok(CommandResult::Exit(exit_code))
+        }
+        CliSubcommands::ShellInit(arg) => {
+            print_shell_init(arg.shell)?;
+            Ok(CommandResult::Done)
+        }
+        CliSubcommands::Spotify(arg) => {
+            credentials::load(config).await?;
+            match arg.subcommands {
+                CliSpotifySubcommands::Client(arg) => {
+                    set_spotify_client(&mut config.spotify, &config.credentials, arg).await?;
+                    Ok(CommandResult::Done)
+                }
+                CliSpotifySubcommands::Login(arg) => {
+                    spotify_login(config, arg.no_browser).await?;
+                    Ok(CommandResult::Done)
+                }
+                CliSpotifySubcommands::Logout => {
+                    spotify_logout(&mut config.spotify, &config.credentials).await?;
+                    Ok(CommandResult::Done)
+                }
+                CliSpotifySubcommands::Status => {
+                    print_spotify_status(config).await?;
+                    Ok(CommandResult::Done)
+                }
+            }
+        }
         CliSubcommands::Start => unimplemented!(),
     };
 }
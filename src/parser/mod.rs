@@ -1,7 +1,8 @@
 pub mod cli;
 pub mod variables;
 
-use clap::{Args, Parser, Subcommand};
+use crate::config::{ProcessMatcher, ProcessStrategy};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[command(version, about = "Dynamic Discord Rich Presence Customizer")]
@@ -32,12 +33,55 @@ pub enum CliSubcommands {
     Processes(CliProcesses),
     #[command(about = "unimplemented")]
     Refresh,
-    #[command(about = "Manage your Spotify account and app connection\nunimplemented")]
+    #[command(
+        about = "Print shell hooks that report the running command and directory for {{shell.*}}. Add\n`eval \"$(ddrpc shell-init bash)\"` to your shell's startup file (`ddrpc shell-init fish | source` for fish)"
+    )]
+    ShellInit(CliShellInit),
+    #[command(about = "Manage your Spotify account and app connection")]
     Spotify(CliSpotify),
+    #[command(
+        about = "Run a command and show it in the Discord activity while it runs. Exits with the command's exit code"
+    )]
+    Run(CliRun),
     #[command(about = "unimplemented")]
     Start,
 }
 
+#[derive(Debug, Args)]
+pub struct CliRun {
+    #[arg(
+        short = 'd',
+        long,
+        help = "Activity details while the command runs. Defaults to task.details from the config"
+    )]
+    pub details: Option<String>,
+    #[arg(
+        short = 'n',
+        long,
+        help = "Task name for {{task.name}}. Defaults to the program name"
+    )]
+    pub name: Option<String>,
+    #[arg(
+        required = true,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        help = "Command to run"
+    )]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CliShellInit {
+    pub shell: CliShell,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CliShell {
+    Bash,
+    Fish,
+    Zsh,
+}
+
 #[derive(Debug, Args)]
 pub struct CliDiscord {
     #[command(subcommand)]
@@ -45,11 +89,12 @@ pub struct CliDiscord {
 }
 
 #[derive(Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum CliDiscordSubcommands {
     #[command(
         about = "Start Discord IPC client and set activity. Will start activity update loop"
     )]
-    Connect,
+    Connect(CliDiscordConnect),
     #[command(about = "Clear the Discord activity\nUnimplemented")]
     Disconnect,
     #[command(about = "Get Discord activity data")]
@@ -62,6 +107,15 @@ pub enum CliDiscordSubcommands {
     Update,
 }
 
+#[derive(Debug, Args)]
+pub struct CliDiscordConnect {
+    #[arg(
+        long,
+        help = "Print the Spotify authorization URL instead of opening a browser, if Spotify has to be authorized"
+    )]
+    pub no_browser: bool,
+}
+
 #[derive(Debug, Args)]
 pub struct CliDiscordGet {
     #[arg(short = 'd', long, help = "Unimplemented")]
@@ -104,29 +158,159 @@ pub struct CliProcesses {
 pub enum CliProcessesSubcommands {
     #[command(about = "Add a process")]
     Add(CliProcessesAdd),
+    #[command(about = "Find and remove target processes with duplicate or empty names")]
+    Dedupe(CliProcessesDedupe),
+    #[command(about = "Add processes for installed applications found in .desktop files")]
+    Discover(CliProcessesDiscover),
+    #[command(about = "Edit a process")]
+    Edit(CliProcessesEdit),
+    #[command(about = "Export target processes as TOML or JSON")]
+    Export(CliProcessesExport),
+    #[command(about = "Import target processes from a TOML or JSON file")]
+    Import(CliProcessesImport),
     #[command(about = "List all target processes")]
     List,
     #[command(about = "Reorder target process priorities")]
     Priority(CliProcessesPriority),
     #[command(about = "Remove a process")]
     Remove(CliProcessesRemove),
-    #[command(about = "Show all active processes (may not include)")]
-    Show,
+    #[command(
+        about = "Set how the shown process is chosen when multiple target processes are active"
+    )]
+    Strategy(CliProcessesStrategy),
+    #[command(about = "Show running processes and which target processes match them")]
+    Show(CliProcessesShow),
+    #[command(about = "Test a process name against running processes before adding it")]
+    Test(CliProcessesTest),
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct CliProcessesAdd {
+    #[arg(
+        short = 'a',
+        long,
+        help = "Seconds the process has to be running before it is shown"
+    )]
+    pub appear_after: Option<u64>,
     #[arg(
         index = 3,
         help = "Set the image URL or Discord asset name for the process"
     )]
     pub image: String,
-    #[arg(index = 1, help = "Name of the process being added")]
+    #[arg(
+        short = 'l',
+        long,
+        help = "Seconds the process is still shown for after it exits"
+    )]
+    pub linger_for: Option<u64>,
+    #[arg(short = 'm', long, value_enum, default_value_t = ProcessMatcher::Name, help = "What the process name is compared against")]
+    pub matcher: ProcessMatcher,
+    #[arg(index = 1, help = "Name or application ID of the process being added")]
     pub name: String,
     #[arg(index = 2, help = "Set the text associated with this process")]
     pub text: String,
 }
 
+#[derive(Debug, Args)]
+pub struct CliProcessesDedupe {
+    #[arg(short = 'f', long, help = "Remove the duplicate and invalid processes")]
+    pub fix: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesDiscover {
+    #[arg(
+        short = 'a',
+        long,
+        help = "Add every installed application without asking"
+    )]
+    pub all_installed: bool,
+    #[arg(
+        short = 'f',
+        long,
+        help = "Only offer applications whose name or desktop file ID contains this text"
+    )]
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesEdit {
+    #[arg(help = "Name of the process being edited")]
+    pub name: String,
+    #[arg(
+        short = 'a',
+        long,
+        help = "Seconds the process has to be running before it is shown"
+    )]
+    pub appear_after: Option<u64>,
+    #[arg(
+        short = 'i',
+        long,
+        help = "Set the image URL or Discord asset name for the process"
+    )]
+    pub image: Option<String>,
+    #[arg(
+        short = 'l',
+        long,
+        help = "Seconds the process is still shown for after it exits"
+    )]
+    pub linger_for: Option<u64>,
+    #[arg(
+        short = 'm',
+        long,
+        value_enum,
+        help = "Set what the process name is compared against"
+    )]
+    pub matcher: Option<ProcessMatcher>,
+    #[arg(short = 'r', long, help = "Set a new name for the process")]
+    pub rename: Option<String>,
+    #[arg(
+        long,
+        help = "Use the global appear_after and linger_for values for this process. --appear-after or --linger-for given along with it are still set"
+    )]
+    pub reset_timings: bool,
+    #[arg(short = 't', long, help = "Set the text associated with this process")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesExport {
+    #[arg(help = "File to write to. Prints to stdout if not given")]
+    pub file: Option<String>,
+    #[arg(
+        short = 'f',
+        long,
+        value_enum,
+        help = "File format. Guessed from the file extension if not given"
+    )]
+    pub format: Option<CliProcessesFormat>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CliProcessesFormat {
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesImport {
+    #[arg(help = "File to read processes from")]
+    pub file: String,
+    #[arg(
+        short = 'f',
+        long,
+        value_enum,
+        help = "File format. Guessed from the file extension if not given"
+    )]
+    pub format: Option<CliProcessesFormat>,
+    #[arg(
+        short = 'r',
+        long,
+        help = "Replace all target processes instead of adding to them"
+    )]
+    pub replace: bool,
+}
+
 #[derive(Debug, Args)]
 pub struct CliProcessesPriority {
     #[arg(help = "Name of the process entry operated on")]
@@ -156,6 +340,46 @@ pub struct CliProcessesRemove {
     pub name: String,
 }
 
+#[derive(Debug, Args)]
+pub struct CliProcessesShow {
+    #[arg(
+        short = 'f',
+        long,
+        help = "Only show processes whose name or command line contains this text"
+    )]
+    pub filter: Option<String>,
+    #[arg(
+        short = 'm',
+        long,
+        help = "Only show processes matched by a target process"
+    )]
+    pub matched: bool,
+    #[arg(short = 's', long, value_enum, default_value_t = CliProcessesSort::Name, help = "Sort processes by this field")]
+    pub sort: CliProcessesSort,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CliProcessesSort {
+    Name,
+    Pid,
+    Cpu,
+    Memory,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesStrategy {
+    #[arg(value_enum, help = "Process priority strategy")]
+    pub strategy: ProcessStrategy,
+}
+
+#[derive(Debug, Args)]
+pub struct CliProcessesTest {
+    #[arg(help = "Process name or application ID to test")]
+    pub pattern: String,
+    #[arg(short = 'm', long, value_enum, default_value_t = ProcessMatcher::Name, help = "What the pattern is compared against")]
+    pub matcher: ProcessMatcher,
+}
+
 #[derive(Debug, Args)]
 pub struct CliSpotify {
     #[command(subcommand)]
@@ -164,21 +388,51 @@ pub struct CliSpotify {
 
 #[derive(Debug, Subcommand)]
 pub enum CliSpotifySubcommands {
-    #[command(about = "Link your spotify account???")]
-    Add,
-    #[command(about = "Set Spotify application data")]
+    #[command(
+        about = "Set the Spotify app's client ID and secret. Prints them if no option is given"
+    )]
     Client(CliSpotifyClient),
-    #[command(about = "Unlink your spotify account???")]
-    Remove,
+    #[command(about = "Authorize ddrpc with your Spotify account and save the refresh token")]
+    Login(CliSpotifyLogin),
+    #[command(about = "Remove the saved Spotify tokens")]
+    Logout,
+    #[command(
+        about = "Show the Spotify account, granted scopes, token expiry and what is playing"
+    )]
+    Status,
 }
 
 #[derive(Debug, Args)]
+pub struct CliSpotifyLogin {
+    #[arg(
+        long,
+        help = "Print the Spotify authorization URL instead of opening a browser"
+    )]
+    pub no_browser: bool,
+}
+
+#[derive(Args)]
 pub struct CliSpotifyClient {
-    #[arg(help = "Set Spotify client ID")]
+    #[arg(short = 'i', long, help = "Spotify client ID")]
     pub id: Option<String>,
-    #[arg(help = "Set Spotify client secret")]
+    #[arg(
+        short = 's',
+        long,
+        help = "Spotify client secret. Set it to \"\" to authorize with PKCE, which only needs the client ID"
+    )]
     pub secret: Option<String>,
 }
+
+/// Leaves the secret out, as the parsed command is logged
+impl std::fmt::Debug for CliSpotifyClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("CliSpotifyClient")
+            .field("id", &self.id)
+            .field("secret", &self.secret.as_ref().map(|_| "<Redacted>"))
+            .finish();
+    }
+}
 /*
 CLI
 |- discord
@@ -217,3 +471,87 @@ CLI
 |- ping
 |- refresh
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spotify_client(args: &[&str]) -> CliSpotifyClient {
+        let cli: Cli = Cli::try_parse_from(args).unwrap();
+        let CliSubcommands::Spotify(CliSpotify {
+            subcommands: CliSpotifySubcommands::Client(client),
+        }) = cli.subcommands
+        else {
+            panic!("{args:?} is not a spotify client command");
+        };
+        return client;
+    }
+
+    #[test]
+    fn spotify_client_secret_is_redacted() {
+        let client: CliSpotifyClient = spotify_client(&[
+            "ddrpc", "spotify", "client", "--id", "abc", "--secret", "hunter2",
+        ]);
+        assert_eq!(client.id.as_deref(), Some("abc"));
+        assert_eq!(client.secret.as_deref(), Some("hunter2"));
+        assert_eq!(
+            format!("{client:?}"),
+            "CliSpotifyClient { id: Some(\"abc\"), secret: Some(\"<Redacted>\") }"
+        );
+
+        // An empty secret switches to PKCE
+        let client: CliSpotifyClient = spotify_client(&["ddrpc", "spotify", "client", "-s", ""]);
+        assert_eq!(client.id, None);
+        assert_eq!(client.secret.as_deref(), Some(""));
+        assert_eq!(
+            format!("{client:?}"),
+            "CliSpotifyClient { id: None, secret: Some(\"<Redacted>\") }"
+        );
+
+        let client: CliSpotifyClient = spotify_client(&["ddrpc", "spotify", "client", "-i", "abc"]);
+        assert_eq!(client.secret, None);
+        assert_eq!(
+            format!("{client:?}"),
+            "CliSpotifyClient { id: Some(\"abc\"), secret: None }"
+        );
+    }
+
+    #[test]
+    fn spotify_subcommands() {
+        let subcommand = |args: &[&str]| -> CliSpotifySubcommands {
+            let cli: Cli = Cli::try_parse_from([&["ddrpc", "spotify"], args].concat()).unwrap();
+            let CliSubcommands::Spotify(spotify) = cli.subcommands else {
+                panic!("{args:?} is not a spotify subcommand");
+            };
+            return spotify.subcommands;
+        };
+        assert!(matches!(
+            subcommand(&["login"]),
+            CliSpotifySubcommands::Login(CliSpotifyLogin { no_browser: false })
+        ));
+        assert!(matches!(
+            subcommand(&["login", "--no-browser"]),
+            CliSpotifySubcommands::Login(CliSpotifyLogin { no_browser: true })
+        ));
+        assert!(matches!(
+            subcommand(&["logout"]),
+            CliSpotifySubcommands::Logout
+        ));
+        assert!(matches!(
+            subcommand(&["status"]),
+            CliSpotifySubcommands::Status
+        ));
+        assert!(Cli::try_parse_from(["ddrpc", "spotify", "add"]).is_err());
+        // Only commands that can authorize Spotify take --no-browser
+        assert!(Cli::try_parse_from(["ddrpc", "spotify", "status", "--no-browser"]).is_err());
+        assert!(Cli::try_parse_from(["ddrpc", "--no-browser", "ping"]).is_err());
+        let cli: Cli =
+            Cli::try_parse_from(["ddrpc", "discord", "connect", "--no-browser"]).unwrap();
+        assert!(matches!(
+            cli.subcommands,
+            CliSubcommands::Discord(CliDiscord {
+                subcommands: CliDiscordSubcommands::Connect(CliDiscordConnect { no_browser: true })
+            })
+        ));
+    }
+}
@@ -1,32 +1,81 @@
 use crate::{
+    media::MediaData,
     prelude::*,
-    processes::{get_active_data, get_names},
-    spotify::{get_currently_playing_track, TrackData},
+    processes::{format_duration, format_memory, get_active_data, AutoApp, ProcessData},
+    shell::ShellData,
+    spotify::fallback_variables,
+    task::TaskState,
+    workspace::WorkspaceData,
 };
-use rspotify::AuthCodeSpotify;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Create the hashmap for template variables and their replacements in Discord data.
 #[instrument(skip_all)]
 pub async fn template_hashmap<'th>(
     config: &Config,
-    spotify: &Option<AuthCodeSpotify>,
+    processes: &[ProcessData],
+    auto_app: Option<&AutoApp>,
+    media: MediaData,
+    shell: Option<&ShellData>,
+    workspace: WorkspaceData,
+    task: Option<&TaskState>,
 ) -> HashMap<String, String> {
-    let (process_text, process_icon) =
-        get_active_data(&config.processes, &get_names(&config.processes));
-
-    let track = if let Some(client) = spotify {
-        match get_currently_playing_track(client).await.unwrap() {
-            None => TrackData::fallback(&config.spotify.fallback),
-            Some(track_data) => track_data,
-        }
-    } else {
-        TrackData::fallback(&config.spotify.fallback)
-    };
+    let (process_text, process_icon, process_data) = get_active_data(&config.processes, processes);
 
     let mut replace_hashmap: HashMap<String, String> = HashMap::new();
     replace_hashmap.insert(String::from("process.icon"), process_icon);
     replace_hashmap.insert(String::from("process.text"), process_text);
+    replace_hashmap.insert(String::from("process.count"), processes.len().to_string());
+    replace_hashmap.insert(
+        String::from("process.all"),
+        processes
+            .iter()
+            .filter_map(|process: &ProcessData| {
+                config
+                    .processes
+                    .processes
+                    .iter()
+                    .find(|target: &&ProcessConfig| target.name == process.name)
+                    .map(|target: &ProcessConfig| target.text.to_owned())
+            })
+            .collect::<Vec<String>>()
+            .join(", "),
+    );
+    process_variables(&mut replace_hashmap, process_data);
+    replace_hashmap.insert(
+        String::from("auto.app.name"),
+        auto_app
+            .map(|app: &AutoApp| app.name.to_owned())
+            .unwrap_or_default(),
+    );
+    replace_hashmap.insert(
+        String::from("auto.app.icon"),
+        auto_app
+            .map(|app: &AutoApp| app.icon.to_owned())
+            .unwrap_or_default(),
+    );
+    replace_hashmap.insert(String::from("project.name"), workspace.project_name);
+    replace_hashmap.insert(String::from("git.branch"), workspace.branch);
+    replace_hashmap.insert(String::from("git.remote.url"), workspace.remote_url);
+    replace_hashmap.insert(String::from("git.remote.web_url"), workspace.remote_web_url);
+    replace_hashmap.insert(String::from("git.dirty"), workspace.dirty.to_string());
+    task_variables(&mut replace_hashmap, task);
+    replace_hashmap.insert(
+        String::from("shell.command"),
+        shell
+            .map(|shell: &ShellData| shell.command.to_owned())
+            .unwrap_or_default(),
+    );
+    replace_hashmap.insert(
+        String::from("shell.cwd"),
+        shell
+            .map(|shell: &ShellData| shell.cwd.to_owned())
+            .unwrap_or_default(),
+    );
+    replace_hashmap.insert(
+        String::from("shell.idle"),
+        shell.is_none_or(|shell: &ShellData| shell.idle).to_string(),
+    );
     replace_hashmap.insert(
         String::from("idle.icon"),
         config.processes.idle_image.to_owned(),
@@ -36,11 +85,9 @@ pub async fn template_hashmap<'th>(
         config.processes.idle_text.to_owned(),
     );
 
-    replace_hashmap.insert(String::from("spotify.track.name"), track.name);
-    replace_hashmap.insert(String::from("spotify.track.artists"), track.artists);
-    replace_hashmap.insert(String::from("spotify.track.url"), track.track_url);
-    replace_hashmap.insert(String::from("spotify.album.cover"), track.album_cover_url);
-    replace_hashmap.insert(String::from("spotify.album.name"), track.album_name);
+    // Sources only set their own variables, the `spotify.*` ones fall back when the Spotify Web API is not the source
+    replace_hashmap.extend(fallback_variables(&config.spotify.fallback));
+    media_variables(&mut replace_hashmap, media);
 
     trace!("Template variable hashmap created");
     return replace_hashmap
@@ -54,6 +101,91 @@ pub async fn template_hashmap<'th>(
         .collect();
 }
 
+/// Insert the `process.*` variables taken from the chosen process. All of them are empty if no target process is active.
+fn process_variables(replace_hashmap: &mut HashMap<String, String>, process: Option<&ProcessData>) {
+    let variables: [(&str, Option<String>); 9] = [
+        (
+            "process.app_id",
+            process.and_then(|process| process.app_id.to_owned()),
+        ),
+        (
+            "process.pid",
+            process.map(|process| process.pid.to_string()),
+        ),
+        (
+            "process.uptime",
+            process.map(|process| format_duration(process.run_time)),
+        ),
+        (
+            "process.start_time",
+            process.map(|process| process.start_time.to_string()),
+        ),
+        (
+            "process.cpu",
+            process.map(|process| format!("{:.1}%", process.cpu_usage)),
+        ),
+        (
+            "process.memory",
+            process.map(|process| format_memory(process.memory)),
+        ),
+        ("process.cwd", process.map(|process| process.cwd.to_owned())),
+        ("process.exe", process.map(|process| process.exe.to_owned())),
+        (
+            "process.args",
+            process.map(|process| process.args.join(" ")),
+        ),
+    ];
+
+    for (variable, replacement) in variables {
+        replace_hashmap.insert(String::from(variable), replacement.unwrap_or_default());
+    }
+}
+
+/// Insert the `media.*` variables and the source specific variables of the chosen media source.
+fn media_variables(replace_hashmap: &mut HashMap<String, String>, media: MediaData) {
+    let (position, length) = (media.position(), media.length());
+
+    replace_hashmap.extend(media.variables);
+    replace_hashmap.insert(String::from("media.source"), media.source);
+    replace_hashmap.insert(String::from("media.player"), media.player);
+    replace_hashmap.insert(String::from("media.title"), media.title);
+    replace_hashmap.insert(String::from("media.artists"), media.artists);
+    replace_hashmap.insert(String::from("media.album"), media.album);
+    replace_hashmap.insert(String::from("media.art_url"), media.art_url);
+    replace_hashmap.insert(String::from("media.url"), media.url);
+    replace_hashmap.insert(String::from("media.status"), media.status);
+    replace_hashmap.insert(String::from("media.position"), position);
+    replace_hashmap.insert(String::from("media.length"), length);
+}
+
+/// Insert the `task.*` variables taken from the `ddrpc run` task shown in the activity. All of them are empty if there is
+/// no task.
+fn task_variables(replace_hashmap: &mut HashMap<String, String>, task: Option<&TaskState>) {
+    let variables: [(&str, Option<String>); 7] = [
+        ("task.name", task.map(|task| task.name.to_owned())),
+        (
+            "task.details",
+            task.and_then(|task| task.details.to_owned()),
+        ),
+        ("task.command", task.map(|task| task.command.to_owned())),
+        ("task.status", task.map(|task| task.status().to_owned())),
+        (
+            "task.exit_code",
+            task.and_then(|task| task.result.as_ref())
+                .map(|result| result.exit_code.to_string()),
+        ),
+        (
+            "task.elapsed",
+            task.map(|task| format_duration(task.elapsed())),
+        ),
+        ("task.start_time", task.map(|task| task.started.to_string())),
+    ];
+
+    for (variable, replacement) in variables {
+        replace_hashmap.insert(String::from(variable), replacement.unwrap_or_default());
+    }
+}
+
 /// 3+ hours of wasted time Dx it doesn't even work thats the worst part but whatever
 #[instrument(skip_all)]
 fn nested_variables(template_hashmap: HashMap<String, String>) -> HashMap<String, String> {
@@ -87,7 +219,7 @@ fn nested_variables(template_hashmap: HashMap<String, String>) -> HashMap<String
 		for (target, replacement) in &nest_permitted_hashmap {
 			let target = format!("{{{{{}}}}}", target);
 			trace!("Replacing \"{target}\" with \"{replacement}\" in \"{key}\"");
-            string = string.replace(&target, &replacement);
+            string = string.replace(&target, replacement);
 		}
 
         return (key.to_owned(), string);
@@ -113,3 +245,50 @@ pub fn replace_template_variables(
 
     return string;
 }
+
+/// Names of the template variables used in the config, like `spotify.track.name` for `{{spotify.track.name}}`. Used to
+/// only fetch data that is shown. Values of other variables can contain templates too, like a process text, so those
+/// are included.
+pub fn template_variables(config: &Config) -> HashSet<String> {
+    let discord: &DiscordConfig = &config.discord;
+    let fallback: &SpotifyFallbackConfig = &config.spotify.fallback;
+    let mut templates: Vec<&String> = vec![
+        &discord.details,
+        &discord.state,
+        &discord.assets.large_image,
+        &discord.assets.large_text,
+        &discord.assets.small_image,
+        &discord.assets.small_text,
+        &discord.buttons.btn1_text,
+        &discord.buttons.btn1_url,
+        &discord.buttons.btn2_text,
+        &discord.buttons.btn2_url,
+        &config.task.details,
+        &config.task.state,
+        &config.task.finished_state,
+        &config.processes.idle_image,
+        &config.processes.idle_text,
+        &fallback.album_name,
+        &fallback.album_cover_url,
+        &fallback.artists,
+        &fallback.name,
+        &fallback.track_url,
+    ];
+    for process in &config.processes.processes {
+        templates.push(&process.image);
+        templates.push(&process.text);
+    }
+
+    let mut variables: HashSet<String> = HashSet::new();
+    for template in templates {
+        let mut rest: &str = template;
+        while let Some((_, after)) = rest.split_once("{{") {
+            let Some((variable, after)) = after.split_once("}}") else {
+                break;
+            };
+            variables.insert(variable.to_owned());
+            rest = after;
+        }
+    }
+    return variables;
+}
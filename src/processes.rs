@@ -1,47 +1,482 @@
-use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    io::Write,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    parser::{CliProcessesAdd, CliProcessesPriority, CliProcessesPriorityOperation},
+    desktop::{installed_applications, DesktopEntry},
+    parser::{
+        CliProcessesAdd, CliProcessesDiscover, CliProcessesEdit, CliProcessesExport,
+        CliProcessesFormat, CliProcessesImport, CliProcessesPriority,
+        CliProcessesPriorityOperation, CliProcessesShow, CliProcessesSort, CliProcessesTest,
+    },
     prelude::*,
+    sandbox,
 };
-use sysinfo::{ProcessRefreshKind, RefreshKind, System};
+use sysinfo::{Pid, Process, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
-/// Creates a vector of all found target processes. Processes are searched for by process name from `ProcessesConfig`.
-#[instrument(skip_all)]
-pub fn get_names(config: &ProcessesConfig) -> Vec<String> {
-    let sys = System::new_with_specifics(
-        RefreshKind::new().with_processes(ProcessRefreshKind::everything()),
-    );
+/// How long installed applications are kept before auto mode reads them again, so applications installed while ddrpc
+/// runs are found
+const APPLICATIONS_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Data for a running process matched by a `ProcessConfig` entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessData {
+    /// Name of the `ProcessConfig` entry that matched this process
+    pub name: String,
+    /// Application ID found by [`sandbox::app_id`]
+    pub app_id: Option<String>,
+    pub pid: u32,
+    /// Seconds since the Unix epoch
+    pub start_time: u64,
+    /// Seconds since the process started
+    pub run_time: u64,
+    /// CPU usage in percent of one core
+    pub cpu_usage: f32,
+    /// Memory usage in bytes
+    pub memory: u64,
+    pub cwd: String,
+    pub exe: String,
+    pub args: Vec<String>,
+}
+
+impl ProcessData {
+    fn new(name: &str, process: &Process, app_id: Option<String>) -> Self {
+        return Self {
+            name: name.to_owned(),
+            app_id,
+            pid: process.pid().as_u32(),
+            start_time: process.start_time(),
+            run_time: process.run_time(),
+            cpu_usage: process.cpu_usage(),
+            memory: process.memory(),
+            cwd: process
+                .cwd()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            exe: process
+                .exe()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            args: process.cmd().iter().skip(1).cloned().collect(),
+        };
+    }
+
+    /// Whether both are the same process matched by the same target process.
+    fn is_same(&self, other: &ProcessData) -> bool {
+        return self.pid == other.pid && self.name == other.name;
+    }
+
+    /// Copy without the CPU usage, memory usage and run time, which change on every scan.
+    pub fn without_usage(&self) -> Self {
+        return Self {
+            run_time: 0,
+            cpu_usage: 0.0,
+            memory: 0,
+            ..self.to_owned()
+        };
+    }
+}
+
+/// A change in the set of active target processes between two scans.
+#[derive(Clone, Debug)]
+pub enum ProcessEvent {
+    Started(ProcessData),
+    Exited(ProcessData),
+}
+
+/// Long-lived process scanner. Keeps its `System` between scans so that only the process list and the attributes of
+/// matched target processes are refreshed, and so that CPU usage can be calculated between scans.
+pub struct ProcessScanner {
+    system: System,
+    /// Target processes shown after the last scan, in config order
+    active: Vec<ProcessData>,
+    /// Target processes that have been seen and are either running or lingering, by target process name
+    tracked: HashMap<String, TrackedProcess>,
+    /// Installed applications by process name and when they were read. Read when auto mode is used, and again after
+    /// [`APPLICATIONS_MAX_AGE`]
+    applications: Option<(Instant, HashMap<String, DesktopEntry>)>,
+    /// Application chosen by auto mode during the last scan
+    auto: Option<AutoApp>,
+    /// Application IDs of processes that have been checked with the process start time, which do not change while a
+    /// process is running. The start time detects a reused PID
+    app_ids: HashMap<Pid, (u64, Option<String>)>,
+}
+
+/// Running application chosen by auto mode when no target process is active.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoApp {
+    pub name: String,
+    pub icon: String,
+    pub process: ProcessData,
+}
+
+/// Application ID of a process from `app_ids`, which is only read from the process's cgroup if the process has not
+/// been checked yet or its PID was reused by a process with a different start time.
+fn cached_app_id<'a>(
+    app_ids: &'a mut HashMap<Pid, (u64, Option<String>)>,
+    process: &Process,
+) -> &'a Option<String> {
+    let (start_time, app_id) = app_ids.entry(process.pid()).or_insert_with(|| {
+        return (
+            process.start_time(),
+            sandbox::app_id(process.pid().as_u32()),
+        );
+    });
+    if *start_time != process.start_time() {
+        *start_time = process.start_time();
+        *app_id = sandbox::app_id(process.pid().as_u32());
+    }
+    return app_id;
+}
+
+/// Timing data for applying `appear_after` and `linger_for` to a target process.
+struct TrackedProcess {
+    data: ProcessData,
+    /// When the process started, or when a previous matching process started if it was restarted while lingering
+    first_seen: Instant,
+    last_seen: Instant,
+}
+
+impl Default for ProcessScanner {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl ProcessScanner {
+    pub fn new() -> Self {
+        return Self {
+            system: System::new(),
+            active: Vec::new(),
+            tracked: HashMap::new(),
+            applications: None,
+            auto: None,
+            app_ids: HashMap::new(),
+        };
+    }
+
+    /// Target processes shown after the last scan, in config order.
+    pub fn active(&self) -> &[ProcessData] {
+        return &self.active;
+    }
+
+    /// Application chosen by auto mode during the last scan. Always `None` if a target process is active.
+    pub fn auto(&self) -> Option<&AutoApp> {
+        return self.auto.as_ref();
+    }
 
-    let mut active_target_processes: Vec<String> = Vec::new();
+    /// Refreshes the process list and finds all target processes from `ProcessesConfig`. Processes are searched for by
+    /// process name or application ID. A process is only shown once it has been running for `appear_after` seconds, and is still shown for
+    /// `linger_for` seconds after it exits. Returns the shown target processes that started or exited since the last scan.
+    /// Auto mode only runs if `auto` is set, as it scans every running process.
+    #[instrument(skip_all)]
+    pub fn scan(&mut self, config: &ProcessesConfig, auto: bool) -> Vec<ProcessEvent> {
+        // Names are always refreshed, which is all that matching needs
+        self.system
+            .refresh_processes_specifics(ProcessRefreshKind::new());
+        self.app_ids
+            .retain(|pid: &Pid, _| self.system.process(*pid).is_some());
 
-    for process in &config.processes {
-        let mut p = sys.processes_by_exact_name(&process.name);
-        if let Some(found_process) = p.next() {
-            active_target_processes.push(found_process.name().to_owned());
+        let mut matched: Vec<(&str, Pid)> = Vec::new();
+        for target in &config.processes {
+            if let Some(pid) = self.find_target(target) {
+                matched.push((&target.name, pid));
+            }
+        }
+
+        let pids: Vec<Pid> = matched.iter().map(|(_, pid)| *pid).collect();
+        self.system.refresh_pids_specifics(
+            &pids,
+            ProcessRefreshKind::new()
+                .with_cpu()
+                .with_memory()
+                .with_cmd(UpdateKind::OnlyIfNotSet)
+                .with_exe(UpdateKind::OnlyIfNotSet)
+                .with_cwd(UpdateKind::Always),
+        );
+
+        let now: Instant = Instant::now();
+        for (name, pid) in &matched {
+            let app_id: Option<String> = self.app_id(*pid);
+            let Some(process) = self.system.process(*pid) else {
+                continue;
+            };
+            let data: ProcessData = ProcessData::new(name, process, app_id);
+            match self.tracked.get_mut(*name) {
+                Some(tracked) => {
+                    tracked.data = data;
+                    tracked.last_seen = now;
+                }
+                None => {
+                    let first_seen: Instant = now
+                        .checked_sub(Duration::from_secs(data.run_time))
+                        .unwrap_or(now);
+                    self.tracked.insert(
+                        name.to_string(),
+                        TrackedProcess {
+                            data,
+                            first_seen,
+                            last_seen: now,
+                        },
+                    );
+                }
+            }
+        }
+
+        self.tracked
+            .retain(|name: &String, tracked: &mut TrackedProcess| {
+                match config
+                    .processes
+                    .iter()
+                    .find(|target: &&ProcessConfig| &target.name == name)
+                {
+                    None => false,
+                    Some(target) => {
+                        let linger_for: u64 = target.linger_for.unwrap_or(config.linger_for);
+                        now.duration_since(tracked.last_seen) <= Duration::from_secs(linger_for)
+                    }
+                }
+            });
+
+        let active: Vec<ProcessData> = config
+            .processes
+            .iter()
+            .filter_map(|target: &ProcessConfig| {
+                let tracked: &TrackedProcess = self.tracked.get(&target.name)?;
+                let appear_after: u64 = target.appear_after.unwrap_or(config.appear_after);
+                if now.duration_since(tracked.first_seen) < Duration::from_secs(appear_after) {
+                    return None;
+                }
+                return Some(tracked.data.to_owned());
+            })
+            .collect();
+
+        let mut events: Vec<ProcessEvent> = Vec::new();
+        for process in &self.active {
+            if !active.iter().any(|new: &ProcessData| new.is_same(process)) {
+                events.push(ProcessEvent::Exited(process.to_owned()));
+            }
         }
+        for process in &active {
+            if !self
+                .active
+                .iter()
+                .any(|old: &ProcessData| old.is_same(process))
+            {
+                events.push(ProcessEvent::Started(process.to_owned()));
+            }
+        }
+
+        trace!(
+            "Found target processes: {:?}",
+            active
+                .iter()
+                .map(|process: &ProcessData| &process.name)
+                .collect::<Vec<&String>>()
+        );
+        if !events.is_empty() {
+            debug!("Target process changes: {events:?}");
+        }
+
+        self.active = active;
+        self.auto = if auto && self.active.is_empty() && config.auto.enabled {
+            self.scan_auto(&config.auto)
+        } else {
+            None
+        };
+        return events;
+    }
+
+    /// Finds the first running process matched by a target process. For application IDs, the sandbox's `bwrap` process
+    /// is only used if no other process has the ID.
+    fn find_target(&mut self, target: &ProcessConfig) -> Option<Pid> {
+        if target.matcher == ProcessMatcher::Name {
+            return self
+                .system
+                .processes_by_exact_name(&target.name)
+                .next()
+                .map(|process: &Process| process.pid());
+        }
+
+        let mut found: Option<Pid> = None;
+        for (pid, process) in self.system.processes() {
+            let app_id: &Option<String> = cached_app_id(&mut self.app_ids, process);
+            if app_id.as_ref() != Some(&target.name) {
+                continue;
+            }
+            if process.name() != "bwrap" {
+                return Some(*pid);
+            }
+            found.get_or_insert(*pid);
+        }
+        return found;
     }
 
-    trace!("Found target processes: {active_target_processes:?}");
+    /// Application ID of a process, read once per process.
+    fn app_id(&mut self, pid: Pid) -> Option<String> {
+        let process: &Process = self.system.process(pid)?;
+        return cached_app_id(&mut self.app_ids, process).to_owned();
+    }
+
+    /// Finds running processes that belong to an installed application and chooses one using `AutoConfig::rank`, or the
+    /// lowest PID if they rank the same. Processes in `AutoConfig::denylist` are ignored.
+    #[instrument(skip_all)]
+    fn scan_auto(&mut self, config: &AutoConfig) -> Option<AutoApp> {
+        if self.applications.as_ref().is_none_or(
+            |(read, _): &(Instant, HashMap<String, DesktopEntry>)| {
+                read.elapsed() >= APPLICATIONS_MAX_AGE
+            },
+        ) {
+            let installed: HashMap<String, DesktopEntry> = installed_applications()
+                .into_iter()
+                .filter_map(|application: DesktopEntry| {
+                    Some((application.process_name()?, application))
+                })
+                .collect();
+            self.applications = Some((Instant::now(), installed));
+        }
+        let (_, applications) = self.applications.as_ref()?;
+
+        let candidates: Vec<Pid> = self
+            .system
+            .processes()
+            .values()
+            .filter(|process: &&Process| {
+                applications.contains_key(process.name())
+                    && !config
+                        .denylist
+                        .iter()
+                        .any(|name: &String| name == process.name())
+            })
+            .map(|process: &Process| process.pid())
+            .collect();
+
+        if candidates.is_empty() {
+            trace!("No running applications found for auto mode");
+            return None;
+        }
+
+        self.system
+            .refresh_pids_specifics(&candidates, ProcessRefreshKind::new().with_cpu());
+
+        let chosen: &Process = candidates
+            .iter()
+            .filter_map(|pid: &Pid| self.system.process(*pid))
+            .max_by(|a: &&Process, b: &&Process| {
+                let rank: Ordering = match config.rank {
+                    AutoRank::Cpu => a.cpu_usage().total_cmp(&b.cpu_usage()),
+                    AutoRank::StartTime => a.start_time().cmp(&b.start_time()),
+                };
+                // Idle applications often rank the same, the lowest PID keeps the choice stable between scans
+                return rank.then_with(|| b.pid().cmp(&a.pid()));
+            })?;
+        let application: &DesktopEntry = applications.get(chosen.name())?;
 
-    return active_target_processes;
+        trace!("Auto mode chose {} ({})", application.name, chosen.pid());
+        return Some(AutoApp {
+            name: application.name.to_owned(),
+            icon: application.icon.to_owned(),
+            process: ProcessData::new(chosen.name(), chosen, application.flatpak_id.to_owned()),
+        });
+    }
+}
+
+/// Chooses which of the active target processes is shown, using `ProcessesConfig::strategy`. Returns the chosen process and
+/// the reason it was chosen.
+pub fn choose_process<'p>(
+    config: &ProcessesConfig,
+    processes: &'p [ProcessData],
+) -> Option<(&'p ProcessData, String)> {
+    let chosen: Option<(&ProcessData, String)> = match config.strategy {
+        ProcessStrategy::ConfigOrder => processes
+            .first()
+            .map(|process: &ProcessData| (process, String::from("highest priority in config"))),
+        ProcessStrategy::MostRecent => processes
+            .iter()
+            .rev()
+            .max_by_key(|process: &&ProcessData| process.start_time)
+            .map(|process: &ProcessData| (process, String::from("most recently started"))),
+        ProcessStrategy::HighestCpu => processes
+            .iter()
+            .rev()
+            .max_by(|a: &&ProcessData, b: &&ProcessData| a.cpu_usage.total_cmp(&b.cpu_usage))
+            .map(|process: &ProcessData| {
+                (
+                    process,
+                    format!("highest CPU usage ({:.1}%)", process.cpu_usage),
+                )
+            }),
+        ProcessStrategy::LongestRunning => processes
+            .iter()
+            .rev()
+            .max_by_key(|process: &&ProcessData| process.run_time)
+            .map(|process: &ProcessData| {
+                (
+                    process,
+                    format!(
+                        "running the longest ({})",
+                        format_duration(process.run_time)
+                    ),
+                )
+            }),
+    };
+
+    return chosen;
 }
 
-/// Returns a tuple with the process text and process icon of the first active process found by `get_names()`.
+/// Returns a tuple with the process text, process icon and process data of the active process chosen by [`choose_process`].
 #[instrument(skip_all)]
-pub fn get_active_data(config: &ProcessesConfig, processes: &Vec<String>) -> (String, String) {
-    for target_process in &config.processes {
-        if processes.first() == Some(&target_process.name) {
-            trace!("Process chosen: {target_process:?}");
-            return (
-                target_process.text.to_owned(),
-                target_process.image.to_owned(),
-            );
+pub fn get_active_data<'p>(
+    config: &ProcessesConfig,
+    processes: &'p [ProcessData],
+) -> (String, String, Option<&'p ProcessData>) {
+    if let Some((active_process, reason)) = choose_process(config, processes) {
+        for target_process in &config.processes {
+            if active_process.name == target_process.name {
+                debug!("Process {} chosen: {reason}", target_process.name);
+                trace!("Process chosen: {target_process:?}");
+                return (
+                    target_process.text.to_owned(),
+                    target_process.image.to_owned(),
+                    Some(active_process),
+                );
+            }
         }
     }
     trace!("No active target processes, using idle data");
 
-    return (config.idle_text.to_owned(), config.idle_image.to_owned());
+    return (
+        config.idle_text.to_owned(),
+        config.idle_image.to_owned(),
+        None,
+    );
+}
+
+/// Formats a duration in seconds as `H:MM:SS`, or `M:SS` if under an hour.
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if hours == 0 {
+        return format!("{minutes}:{seconds:02}");
+    }
+    return format!("{hours}:{minutes:02}:{seconds:02}");
+}
+
+/// Formats a byte count using the largest fitting binary unit, e.g. `512.0 MiB`.
+pub fn format_memory(bytes: u64) -> String {
+    let units: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size: f64 = bytes as f64;
+    let mut unit: usize = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    return format!("{size:.1} {}", units[unit]);
 }
 
 pub fn print_data_list(config: &ProcessesConfig) -> () {
@@ -57,6 +492,228 @@ pub fn print_data_list(config: &ProcessesConfig) -> () {
     }
 }
 
+/// Print running processes along with the target processes matching them. The target process that would be chosen for the
+/// activity is marked as active.
+#[instrument(skip_all)]
+pub fn show_processes(config: &ProcessesConfig, args: CliProcessesShow) -> () {
+    let mut sys = System::new_with_specifics(
+        RefreshKind::new().with_processes(ProcessRefreshKind::everything()),
+    );
+    if matches!(args.sort, CliProcessesSort::Cpu) || config.strategy == ProcessStrategy::HighestCpu
+    {
+        // CPU usage is calculated from the difference between two refreshes
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        sys.refresh_processes();
+    }
+
+    let app_ids: HashMap<Pid, Option<String>> = sys
+        .processes()
+        .keys()
+        .map(|pid: &Pid| (*pid, sandbox::app_id(pid.as_u32())))
+        .collect();
+    let matches_target = |target: &ProcessConfig, process: &Process| -> bool {
+        return match target.matcher {
+            ProcessMatcher::Name => target.name == process.name(),
+            ProcessMatcher::AppId => {
+                app_ids.get(&process.pid()).cloned().flatten().as_ref() == Some(&target.name)
+            }
+        };
+    };
+
+    let candidates: Vec<ProcessData> = config
+        .processes
+        .iter()
+        .filter_map(|target: &ProcessConfig| {
+            let mut found: Vec<&Process> = sys
+                .processes()
+                .values()
+                .filter(|process: &&Process| matches_target(target, process))
+                .collect();
+            found.sort_by_key(|process: &&Process| process.name() == "bwrap");
+            found.first().map(|process: &&Process| {
+                ProcessData::new(&target.name, process, app_ids[&process.pid()].to_owned())
+            })
+        })
+        .collect();
+    let active: Option<(&ProcessData, String)> = choose_process(config, &candidates);
+    let filter: Option<String> = args.filter.map(|filter: String| filter.to_lowercase());
+
+    let mut processes: Vec<(&Process, Vec<(usize, &ProcessConfig)>)> = sys
+        .processes()
+        .values()
+        .filter(|process: &&Process| match &filter {
+            None => true,
+            Some(filter) => {
+                process.name().to_lowercase().contains(filter)
+                    || process.cmd().join(" ").to_lowercase().contains(filter)
+                    || app_ids[&process.pid()]
+                        .as_ref()
+                        .is_some_and(|app_id: &String| app_id.to_lowercase().contains(filter))
+            }
+        })
+        .map(|process: &Process| {
+            let matches: Vec<(usize, &ProcessConfig)> = config
+                .processes
+                .iter()
+                .enumerate()
+                .filter(|(_, target)| matches_target(target, process))
+                .collect();
+            (process, matches)
+        })
+        .filter(|(_, matches)| !args.matched || !matches.is_empty())
+        .collect();
+
+    match args.sort {
+        CliProcessesSort::Name => processes.sort_by(|(a, _), (b, _)| {
+            a.name()
+                .to_lowercase()
+                .cmp(&b.name().to_lowercase())
+                .then(a.pid().cmp(&b.pid()))
+        }),
+        CliProcessesSort::Pid => processes.sort_by_key(|(process, _)| process.pid()),
+        CliProcessesSort::Cpu => {
+            processes.sort_by(|(a, _), (b, _)| b.cpu_usage().total_cmp(&a.cpu_usage()))
+        }
+        CliProcessesSort::Memory => {
+            processes.sort_by_key(|(process, _)| std::cmp::Reverse(process.memory()))
+        }
+    }
+
+    if processes.is_empty() {
+        println!("No processes found.");
+        return;
+    }
+
+    for (process, matches) in &processes {
+        println!(
+            "{} ({})\n\tExe: \"{}\"\n\tCommand: \"{}\"\n\tCPU: {:.1}%\n\tMemory: {}",
+            process.name(),
+            process.pid(),
+            process
+                .exe()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            process.cmd().join(" "),
+            process.cpu_usage(),
+            format_memory(process.memory())
+        );
+        if let Some(Some(app_id)) = app_ids.get(&process.pid()) {
+            println!("\tApp ID: \"{app_id}\"");
+        }
+        for (index, target) in matches {
+            let is_active: bool = active.as_ref().is_some_and(|(active, _)| {
+                active.name == target.name && active.pid == process.pid().as_u32()
+            });
+            println!(
+                "\tMatched by process {index} \"{}\"{}",
+                target.text,
+                if is_active { " (active)" } else { "" }
+            );
+        }
+    }
+
+    match active {
+        None => println!("No target processes active, idle data will be used."),
+        Some((active, reason)) => println!(
+            "Active target process: \"{}\" ({} strategy: {reason})",
+            active.name, config.strategy
+        ),
+    }
+}
+
+/// Check if a process name or application ID would match any running process. If nothing matches, similar names are
+/// suggested.
+#[instrument(skip_all)]
+pub fn test_process(config: &ProcessesConfig, args: CliProcessesTest) -> () {
+    let sys = System::new_with_specifics(
+        RefreshKind::new().with_processes(ProcessRefreshKind::everything()),
+    );
+    let pattern: String = args.pattern;
+
+    // Every running process with the name or application ID being compared against
+    let names: Vec<(&Process, String)> = sys
+        .processes()
+        .values()
+        .filter_map(|process: &Process| match args.matcher {
+            ProcessMatcher::Name => Some((process, process.name().to_owned())),
+            ProcessMatcher::AppId => Some((process, sandbox::app_id(process.pid().as_u32())?)),
+        })
+        .collect();
+    let found: Vec<&Process> = names
+        .iter()
+        .filter(|(_, name)| *name == pattern)
+        .map(|(process, _)| *process)
+        .collect();
+
+    if let Some(target) = config
+        .processes
+        .iter()
+        .find(|target: &&ProcessConfig| target.name == pattern)
+    {
+        println!(
+            "\"{pattern}\" is already a target process with the text \"{}\"",
+            target.text
+        );
+    }
+
+    if !found.is_empty() {
+        println!("\"{pattern}\" matches {} running process(es):", found.len());
+        for process in found {
+            println!("\t{} ({})", process.name(), process.pid());
+        }
+        return;
+    }
+
+    println!("\"{pattern}\" does not match any running process");
+
+    let lowercase_pattern: String = pattern.to_lowercase();
+    let mut suggestions: Vec<(usize, &str)> = names
+        .iter()
+        .map(|(_, name)| name.as_str())
+        .filter_map(|name: &str| {
+            let lowercase_name: String = name.to_lowercase();
+            let distance: usize = edit_distance(&lowercase_pattern, &lowercase_name);
+            if lowercase_name.contains(&lowercase_pattern)
+                || lowercase_pattern.contains(&lowercase_name)
+            {
+                Some((distance.min(1), name))
+            } else if distance <= (pattern.len() / 3).max(2) {
+                Some((distance, name))
+            } else {
+                None
+            }
+        })
+        .collect();
+    suggestions.sort();
+    suggestions.dedup_by(|(_, a), (_, b)| a == b);
+
+    if suggestions.is_empty() {
+        return;
+    }
+
+    println!("Did you mean:");
+    for (_, name) in suggestions.iter().take(5) {
+        println!("\t{name}");
+    }
+}
+
+/// Levenshtein distance between two strings, used for process name suggestions.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution: usize = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    return previous[b.len()];
+}
+
 #[instrument(skip_all)]
 pub fn add_process(
     config: &mut ProcessesConfig,
@@ -65,11 +722,16 @@ pub fn add_process(
     let trace_data: CliProcessesAdd = args.clone();
     let index: usize = config.processes.len();
 
-    config.processes.push(ProcessConfig {
+    let process: ProcessConfig = ProcessConfig {
+        appear_after: args.appear_after,
         image: args.image,
+        linger_for: args.linger_for,
+        matcher: args.matcher,
         name: args.name,
         text: args.text,
-    });
+    };
+    validate_process(config, &process, None)?;
+    config.processes.push(process);
 
     trace!("Added new process {trace_data:?} to processes list at index {index}");
 
@@ -164,6 +826,18 @@ pub fn change_process_priority(
     };
 }
 
+pub fn set_process_strategy(
+    config: &mut ProcessesConfig,
+    strategy: ProcessStrategy,
+) -> Result<(), Box<dyn Error>> {
+    config.strategy = strategy;
+
+    trace!("Set process strategy to {strategy}");
+    println!("Set process strategy to {strategy}");
+
+    return write_config(config);
+}
+
 pub fn remove_process(config: &mut ProcessesConfig, name: String) -> Result<(), Box<dyn Error>> {
     if let Some(index) = config
         .processes
@@ -181,16 +855,391 @@ pub fn remove_process(config: &mut ProcessesConfig, name: String) -> Result<(),
     }
 }
 
+/// Overwrites the fields given in `args`. Resetting the timings happens first, so a timing given along with it is kept.
+fn apply_edit(mut process: ProcessConfig, args: &CliProcessesEdit) -> ProcessConfig {
+    if args.reset_timings {
+        process.appear_after = None;
+        process.linger_for = None;
+    }
+    if let Some(name) = &args.rename {
+        process.name = name.to_owned();
+    }
+    if let Some(text) = &args.text {
+        process.text = text.to_owned();
+    }
+    if let Some(image) = &args.image {
+        process.image = image.to_owned();
+    }
+    if let Some(appear_after) = args.appear_after {
+        process.appear_after = Some(appear_after);
+    }
+    if let Some(linger_for) = args.linger_for {
+        process.linger_for = Some(linger_for);
+    }
+    if let Some(matcher) = args.matcher {
+        process.matcher = matcher;
+    }
+
+    return process;
+}
+
+/// Overwrite the given fields of a process entry and write to file.
+#[instrument(skip_all)]
+pub fn edit_process(
+    config: &mut ProcessesConfig,
+    args: CliProcessesEdit,
+) -> Result<(), Box<dyn Error>> {
+    let Some(index) = config
+        .processes
+        .iter()
+        .position(|process: &ProcessConfig| process.name == args.name)
+    else {
+        return Err(Box::new(ProcessQueryError::UnknownProcess(args.name)));
+    };
+
+    let process: ProcessConfig = apply_edit(config.processes[index].to_owned(), &args);
+    validate_process(config, &process, Some(index))?;
+
+    trace!("Edited process {} to {process:?}", args.name);
+    println!("Edited process {}", process.name);
+    config.processes[index] = process;
+
+    return write_config(config);
+}
+
+/// Add process entries from a TOML or JSON file and write to file. Every entry is validated before any are added.
+#[instrument(skip_all)]
+pub fn import_processes(
+    config: &mut ProcessesConfig,
+    args: CliProcessesImport,
+) -> Result<(), Box<dyn Error>> {
+    let format: CliProcessesFormat = match args.format {
+        Some(format) => format,
+        None => CliProcessesFormat::from_path(&args.file),
+    };
+    let contents: String = fs::read_to_string(&args.file)?;
+    debug!("Read process list from {}", args.file);
+
+    let processes: Vec<ProcessConfig> = match format {
+        CliProcessesFormat::Json => serde_json::from_str(&contents)?,
+        CliProcessesFormat::Toml => toml::from_str::<ProcessList>(&contents)?.processes,
+    };
+
+    let mut imported: ProcessesConfig = config.to_owned();
+    if args.replace {
+        imported.processes.clear();
+    }
+    for (index, process) in processes.into_iter().enumerate() {
+        if let Err(error) = validate_process(&imported, &process, None) {
+            return Err(format!("Process {index} in {}: {error}", args.file).into());
+        }
+        imported.processes.push(process);
+    }
+
+    println!(
+        "Imported {} process(es) from {}",
+        imported.processes.len()
+            - if args.replace {
+                0
+            } else {
+                config.processes.len()
+            },
+        args.file
+    );
+    *config = imported;
+
+    return write_config(config);
+}
+
+/// Print process entries as TOML or JSON, or write them to a file.
+#[instrument(skip_all)]
+pub fn export_processes(
+    config: &ProcessesConfig,
+    args: CliProcessesExport,
+) -> Result<(), Box<dyn Error>> {
+    let format: CliProcessesFormat = match (args.format, &args.file) {
+        (Some(format), _) => format,
+        (None, Some(file)) => CliProcessesFormat::from_path(file),
+        (None, None) => CliProcessesFormat::Toml,
+    };
+
+    let serialized: String = match format {
+        CliProcessesFormat::Json => serde_json::to_string_pretty(&config.processes)?,
+        CliProcessesFormat::Toml => toml::to_string(&ProcessList {
+            processes: config.processes.to_owned(),
+        })?,
+    };
+
+    return match args.file {
+        None => {
+            println!("{serialized}");
+            Ok(())
+        }
+        Some(file) => {
+            fs::write(&file, serialized)?;
+            println!("Exported {} process(es) to {file}", config.processes.len());
+            Ok(())
+        }
+    };
+}
+
+/// Report process entries that would fail validation because of duplicate or empty names. If `fix` is true, the later
+/// duplicates and the entries with empty names are removed and the config is written to file.
+#[instrument(skip_all)]
+pub fn dedupe_processes(config: &mut ProcessesConfig, fix: bool) -> Result<(), Box<dyn Error>> {
+    let mut checked: ProcessesConfig = config.to_owned();
+    checked.processes.clear();
+    let mut invalid: usize = 0;
+
+    for (index, process) in config.processes.iter().enumerate() {
+        match validate_process(&checked, process, None) {
+            Err(error) => {
+                invalid += 1;
+                println!("Process {index}: {error}");
+            }
+            Ok(()) => checked.processes.push(process.to_owned()),
+        }
+    }
+
+    if invalid == 0 {
+        println!("No duplicate or invalid processes found.");
+        return Ok(());
+    }
+    if !fix {
+        println!("Found {invalid} duplicate or invalid process(es). Use --fix to remove them.");
+        return Ok(());
+    }
+
+    config.processes = checked.processes;
+    println!("Removed {invalid} duplicate or invalid process(es)");
+
+    return write_config(config);
+}
+
+/// Offer to add a process entry for each installed application found in XDG `.desktop` files. If `all_installed` is true,
+/// entries are added for every application without asking.
+#[instrument(skip_all)]
+pub fn discover_processes(
+    config: &mut ProcessesConfig,
+    args: CliProcessesDiscover,
+) -> Result<(), Box<dyn Error>> {
+    let filter: Option<String> = args.filter.map(|filter: String| filter.to_lowercase());
+    let mut applications: Vec<(DesktopEntry, String, ProcessMatcher)> = installed_applications()
+        .into_iter()
+        .filter(|application: &DesktopEntry| match &filter {
+            None => true,
+            Some(filter) => {
+                application.name.to_lowercase().contains(filter)
+                    || application.id.to_lowercase().contains(filter)
+            }
+        })
+        .filter_map(|application: DesktopEntry| {
+            // Flatpaks are matched by their application ID since the sandbox hides the process name
+            let (name, matcher) = match &application.flatpak_id {
+                Some(flatpak_id) => (flatpak_id.to_owned(), ProcessMatcher::AppId),
+                None => (application.process_name()?, ProcessMatcher::Name),
+            };
+            Some((application, name, matcher))
+        })
+        .collect();
+    applications.sort_by_key(|(application, _, _)| application.name.to_lowercase());
+
+    let stdin = std::io::stdin();
+    let mut added: usize = 0;
+
+    for (application, name, matcher) in applications {
+        let process: ProcessConfig = ProcessConfig {
+            appear_after: None,
+            image: application.icon.to_owned(),
+            linger_for: None,
+            matcher,
+            name,
+            text: application.name.to_owned(),
+        };
+        if validate_process(config, &process, None).is_err() {
+            trace!("Skipping {}, already a target process", application.id);
+            continue;
+        }
+
+        if !args.all_installed {
+            print!(
+                "Add {} ({} \"{}\", icon \"{}\")? [y/N/q] ",
+                process.text,
+                match process.matcher {
+                    ProcessMatcher::Name => "process",
+                    ProcessMatcher::AppId => "app ID",
+                },
+                process.name,
+                process.image
+            );
+            std::io::stdout().flush()?;
+
+            let mut answer: String = String::new();
+            stdin.read_line(&mut answer)?;
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => (),
+                "q" | "quit" => break,
+                _ => continue,
+            }
+        }
+
+        trace!("Adding process {process:?} from {}", application.id);
+        config.processes.push(process);
+        added += 1;
+    }
+
+    if added == 0 {
+        println!("No processes added.");
+        return Ok(());
+    }
+
+    println!("Added {added} process(es)");
+    return write_config(config);
+}
+
+/// Checks a process entry before it is written to the config. `index` is the position of the entry being replaced, if
+/// any, so that it is not compared against itself.
+fn validate_process(
+    config: &ProcessesConfig,
+    process: &ProcessConfig,
+    index: Option<usize>,
+) -> Result<(), ProcessQueryError> {
+    if process.name.trim().is_empty() {
+        return Err(ProcessQueryError::EmptyName);
+    }
+    if config
+        .processes
+        .iter()
+        .enumerate()
+        .any(|(other_index, other)| Some(other_index) != index && other.name == process.name)
+    {
+        return Err(ProcessQueryError::DuplicateProcess(process.name.to_owned()));
+    }
+    return Ok(());
+}
+
+/// Process list file used by `processes import` and `processes export`.
+#[derive(Serialize, Deserialize)]
+struct ProcessList {
+    #[serde(default)]
+    processes: Vec<ProcessConfig>,
+}
+
+impl CliProcessesFormat {
+    /// Guess the format from the file extension, defaulting to TOML.
+    fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".json") {
+            return Self::Json;
+        }
+        return Self::Toml;
+    }
+}
+
 #[derive(Debug)]
 enum ProcessQueryError {
+    DuplicateProcess(String),
+    EmptyName,
     UnknownProcess(String),
 }
 
 impl Display for ProcessQueryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let ProcessQueryError::UnknownProcess(name) = self;
-        return write!(f, "No process named {name} found");
+        return match self {
+            ProcessQueryError::DuplicateProcess(name) => {
+                write!(f, "A process named {name} already exists")
+            }
+            ProcessQueryError::EmptyName => write!(f, "Process name cannot be empty"),
+            ProcessQueryError::UnknownProcess(name) => write!(f, "No process named {name} found"),
+        };
     }
 }
 
 impl Error for ProcessQueryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Cli, CliProcessesSubcommands, CliSubcommands};
+    use clap::Parser;
+
+    fn edit_args(args: &[&str]) -> CliProcessesEdit {
+        let cli: Cli = Cli::parse_from([&["ddrpc", "processes", "edit", "code"], args].concat());
+        return match cli.subcommands {
+            CliSubcommands::Processes(processes) => match processes.subcommands {
+                CliProcessesSubcommands::Edit(edit) => edit,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+    }
+
+    fn timed_process() -> ProcessConfig {
+        return ProcessConfig {
+            appear_after: Some(5),
+            image: String::from("code"),
+            linger_for: Some(10),
+            matcher: ProcessMatcher::Name,
+            name: String::from("code"),
+            text: String::from("Visual Studio Code"),
+        };
+    }
+
+    #[test]
+    fn reset_timings_keeps_other_edits() {
+        let process: ProcessConfig = apply_edit(
+            timed_process(),
+            &edit_args(&["--reset-timings", "--text", "VS Code", "--rename", "codium"]),
+        );
+        assert_eq!(process.appear_after, None);
+        assert_eq!(process.linger_for, None);
+        assert_eq!(process.text, "VS Code");
+        assert_eq!(process.name, "codium");
+    }
+
+    #[test]
+    fn reset_timings_applies_given_timings_after_resetting() {
+        let process: ProcessConfig = apply_edit(
+            timed_process(),
+            &edit_args(&["--linger-for", "3", "--reset-timings"]),
+        );
+        assert_eq!(process.appear_after, None);
+        assert_eq!(process.linger_for, Some(3));
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("code", "code"), 0);
+        assert_eq!(edit_distance("", "code"), 4);
+        assert_eq!(edit_distance("code", ""), 4);
+        assert_eq!(edit_distance("code", "cod"), 1);
+        assert_eq!(edit_distance("code", "codes"), 1);
+        assert_eq!(edit_distance("code", "node"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn edit_distance_is_symmetric_and_counts_characters() {
+        assert_eq!(edit_distance("firefox", "firfox"), 1);
+        assert_eq!(edit_distance("firfox", "firefox"), 1);
+        assert_eq!(edit_distance("blender", "Blender"), 1);
+        // Multi-byte characters count as one edit
+        assert_eq!(edit_distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn focused_strategy_is_refused() {
+        let read = |strategy: &str| {
+            toml::from_str::<ProcessesConfig>(&format!(
+                "idle_image = \"\"\nidle_text = \"\"\nstrategy = \"{strategy}\""
+            ))
+            .map(|config: ProcessesConfig| config.strategy)
+        };
+        assert_eq!(read("highest_cpu").unwrap(), ProcessStrategy::HighestCpu);
+        assert!(read("focused")
+            .unwrap_err()
+            .to_string()
+            .contains("window focus provider"));
+        assert!(read("newest").is_err());
+    }
+}
@@ -0,0 +1,565 @@
+use crate::{
+    daemon::{read_messages, DaemonListener, DaemonMessage},
+    discord::{render_activity, send_activities, DiscordCommand, DiscordState},
+    hooks::{run_hooks, HookEvent},
+    media::{MediaData, MediaState},
+    prelude::*,
+    processes::{choose_process, AutoApp, ProcessData, ProcessEvent, ProcessScanner},
+    schedule::{interval, Demand, TICK},
+    shell::{ShellData, ShellMonitor},
+    task::{handle_message, TaskState},
+    workspace::{get_workspace, WorkspaceData},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
+    task::{spawn_blocking, JoinHandle, JoinSet},
+    time::Interval,
+};
+
+/// Longest time the first activity waits for every data source to report, so it is not set with missing data first
+const FIRST_RENDER_DELAY: Duration = Duration::from_secs(2);
+
+/// Something that changed, published on the event bus by a provider task, the daemon socket, a signal or the Discord
+/// sender.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Update {
+    /// Target processes after a scan, with the ones that started or exited since the previous scan
+    Processes {
+        active: Vec<ProcessData>,
+        auto: Option<AutoApp>,
+        events: Vec<ProcessEvent>,
+    },
+    /// What the source at `index` in `media.order` is playing, and the variables it always sets
+    Media {
+        index: usize,
+        media: Option<MediaData>,
+        variables: HashMap<String, String>,
+    },
+    Shell(Option<ShellData>),
+    Workspace(WorkspaceData),
+    /// A message from `ddrpc run` on the daemon socket
+    Daemon(DaemonMessage),
+    /// A [`TICK`] passed. Only rendered while a task is shown, since its elapsed time changes on its own
+    Tick,
+    /// Setting the activity failed. The presence loop exits with this error
+    DiscordFailed(String),
+    /// SIGINT or SIGTERM was received. The activity is cleared and the presence loop exits
+    Shutdown,
+}
+
+impl Update {
+    /// Data source that published the update. `None` if it is not data.
+    fn source(&self) -> Option<String> {
+        return match self {
+            Update::Processes { .. } => Some(String::from("processes")),
+            Update::Media { index, .. } => Some(format!("media {index}")),
+            Update::Shell(_) => Some(String::from("shell")),
+            Update::Workspace(_) => Some(String::from("workspace")),
+            Update::Daemon(_) | Update::Tick | Update::DiscordFailed(_) | Update::Shutdown => None,
+        };
+    }
+}
+
+/// Latest data published by the provider tasks, which the activity is rendered from.
+#[derive(Debug, Default)]
+pub struct Inputs {
+    pub auto_app: Option<AutoApp>,
+    /// One entry per source in `media.order` that could be set up, in order
+    pub media: Vec<MediaState>,
+    pub processes: Vec<ProcessData>,
+    pub shell: Option<ShellData>,
+    /// Commands reported by `ddrpc run`
+    pub tasks: Vec<TaskState>,
+    pub workspace: WorkspaceData,
+}
+
+impl Inputs {
+    /// Stores the data of an update. Process changes trigger their hooks.
+    fn apply(&mut self, hooks: &[HookConfig], update: Update) -> () {
+        match update {
+            Update::Processes {
+                active,
+                auto,
+                events,
+            } => {
+                for event in events {
+                    run_hooks(
+                        hooks,
+                        &match event {
+                            ProcessEvent::Started(process) => HookEvent::ProcessStarted(process),
+                            ProcessEvent::Exited(process) => HookEvent::ProcessExited(process),
+                        },
+                    );
+                }
+                self.processes = active;
+                self.auto_app = auto;
+            }
+            Update::Media {
+                index,
+                media,
+                variables,
+            } => {
+                if let Some(source) = self.media.get_mut(index) {
+                    source.last = media.map(|media: MediaData| (media, Instant::now()));
+                    source.variables = variables;
+                }
+            }
+            Update::Shell(shell) => self.shell = shell,
+            Update::Workspace(workspace) => self.workspace = workspace,
+            Update::Daemon(message) => handle_message(&mut self.tasks, message),
+            // Not data, handled by the renderer
+            Update::Tick | Update::DiscordFailed(_) | Update::Shutdown => (),
+        }
+    }
+}
+
+/// Runs the presence loop until SIGINT or SIGTERM is received, or setting the activity fails. Every data source the
+/// config uses runs as its own task and publishes its changes to the renderer, so a slow source never delays the others.
+#[instrument(skip_all)]
+pub async fn run(config: Config, app: AppState) -> Result<(), Box<dyn Error>> {
+    let config: Arc<Config> = Arc::new(config);
+    let (updates, mut receiver) = mpsc::unbounded_channel::<Update>();
+    let mut tasks: JoinSet<()> = JoinSet::new();
+    let mut inputs: Inputs = Inputs::default();
+
+    // The workspace follows the directory of the chosen process, which only the process task knows
+    let (cwd_sender, cwd) = watch::channel::<Option<String>>(None);
+    if app.demand.processes {
+        tasks.spawn(watch_processes(
+            Arc::clone(&config),
+            app.processes,
+            app.demand.to_owned(),
+            cwd_sender,
+            updates.clone(),
+        ));
+    }
+    if app.demand.workspace {
+        tasks.spawn(watch_workspace(Arc::clone(&config), cwd, updates.clone()));
+    }
+    if app.demand.shell {
+        tasks.spawn(watch_shell(Arc::clone(&config), app.shell, updates.clone()));
+    }
+    for (index, source) in app.media.into_iter().enumerate() {
+        inputs.media.push(MediaState::new(source.source()));
+        tasks.spawn(source.run(Arc::clone(&config), index, updates.clone()));
+    }
+    if let Some(daemon) = app.daemon {
+        tasks.spawn(watch_daemon(daemon, updates.clone()));
+    }
+    tasks.spawn(clock(updates.clone()));
+    tasks.spawn(watch_signals(updates.clone()));
+
+    let sources: usize = [app.demand.processes, app.demand.workspace, app.demand.shell]
+        .iter()
+        .filter(|used: &&bool| **used)
+        .count()
+        + inputs.media.len();
+
+    let (discord, commands) = mpsc::unbounded_channel::<DiscordCommand>();
+    let sender: JoinHandle<()> =
+        spawn_blocking(move || send_activities(app.discord, commands, updates));
+
+    let result: Result<(), Box<dyn Error>> =
+        render(&config, &mut inputs, sources, &mut receiver, &discord).await;
+
+    tasks.shutdown().await;
+    if result.is_ok() {
+        let _ = discord.send(DiscordCommand::Clear);
+    }
+    drop(discord);
+    let _ = sender.await;
+
+    return result;
+}
+
+/// Renders the activity whenever inputs changed and hands changed activities to the Discord sender. Updates that
+/// arrived together are all applied before rendering once. The first activity waits until all `sources` data sources
+/// have reported, for at most [`FIRST_RENDER_DELAY`].
+async fn render(
+    config: &Config,
+    inputs: &mut Inputs,
+    sources: usize,
+    receiver: &mut UnboundedReceiver<Update>,
+    discord: &UnboundedSender<DiscordCommand>,
+) -> Result<(), Box<dyn Error>> {
+    let mut state: DiscordState = DiscordState::new(0);
+    let started: Instant = Instant::now();
+    let mut reported: HashSet<String> = HashSet::new();
+    let mut rendered: bool = false;
+    // The activity is always set once, even if the config uses no data sources
+    let mut changed: bool = true;
+
+    loop {
+        let ready: bool =
+            rendered || reported.len() >= sources || started.elapsed() >= FIRST_RENDER_DELAY;
+        if changed && ready {
+            if let Some((data, timestamps)) = render_activity(config, inputs, &mut state).await {
+                if discord.send(DiscordCommand::Set(data, timestamps)).is_err() {
+                    return Err("The Discord sender stopped".into());
+                }
+            }
+            rendered = true;
+            changed = false;
+        }
+
+        let Some(update) = receiver.recv().await else {
+            return Ok(());
+        };
+        let mut pending: Vec<Update> = vec![update];
+        while let Ok(update) = receiver.try_recv() {
+            pending.push(update);
+        }
+
+        for update in pending {
+            if let Some(source) = update.source() {
+                reported.insert(source);
+            }
+            match update {
+                Update::Shutdown => {
+                    info!("Shutting down");
+                    return Ok(());
+                }
+                Update::DiscordFailed(error) => return Err(error.into()),
+                Update::Tick => changed |= !inputs.tasks.is_empty(),
+                update => {
+                    inputs.apply(&config.hooks, update);
+                    changed = true;
+                }
+            }
+        }
+    }
+}
+
+/// Publishes an update made from `data`, unless `data` is the same as what was published last. Returns `false` once the
+/// renderer has stopped.
+pub fn publish<T: Clone + PartialEq>(
+    updates: &UnboundedSender<Update>,
+    last: &mut Option<T>,
+    data: T,
+    update: impl FnOnce(T) -> Update,
+) -> bool {
+    if last.as_ref() == Some(&data) {
+        return true;
+    }
+    *last = Some(data.to_owned());
+    return updates.send(update(data)).is_ok();
+}
+
+/// What process scans are compared by to decide whether the renderer needs the new one: the active target processes,
+/// the auto mode application and the PID of the chosen process. Usage changes on every scan, so it is only compared if
+/// the templates show it.
+type ScanKey = (Vec<ProcessData>, Option<AutoApp>, Option<u32>);
+
+fn scan_key(
+    active: &[ProcessData],
+    auto: Option<&AutoApp>,
+    chosen: Option<&ProcessData>,
+    usage: bool,
+) -> ScanKey {
+    let compared = |process: &ProcessData| -> ProcessData {
+        return match usage {
+            false => process.without_usage(),
+            true => process.to_owned(),
+        };
+    };
+
+    return (
+        active.iter().map(compared).collect(),
+        auto.map(|app: &AutoApp| AutoApp {
+            process: compared(&app.process),
+            ..app.to_owned()
+        }),
+        chosen.map(|process: &ProcessData| process.pid),
+    );
+}
+
+/// Scans processes every `processes.interval` seconds, including auto mode if the templates use it. Scans are only
+/// published if they differ from the last one by [`scan_key`] or processes started or exited. The working directory of
+/// the chosen process is shared with the workspace task.
+async fn watch_processes(
+    config: Arc<Config>,
+    mut scanner: ProcessScanner,
+    demand: Demand,
+    cwd: watch::Sender<Option<String>>,
+    updates: UnboundedSender<Update>,
+) -> () {
+    let mut interval: Interval = interval(config.processes.interval);
+    let mut last: Option<ScanKey> = None;
+    loop {
+        interval.tick().await;
+        let events: Vec<ProcessEvent> = scanner.scan(&config.processes, demand.auto);
+
+        let chosen: Option<&ProcessData> = choose_process(&config.processes, scanner.active())
+            .map(|(process, _): (&ProcessData, String)| process);
+        let chosen_cwd: Option<String> = chosen.map(|process: &ProcessData| process.cwd.to_owned());
+        cwd.send_if_modified(|cwd: &mut Option<String>| {
+            if *cwd == chosen_cwd {
+                return false;
+            }
+            *cwd = chosen_cwd;
+            return true;
+        });
+
+        let key: ScanKey = scan_key(
+            scanner.active(),
+            scanner.auto(),
+            chosen,
+            demand.process_usage,
+        );
+        if last.as_ref() == Some(&key) && events.is_empty() {
+            continue;
+        }
+        last = Some(key);
+
+        let update: Update = Update::Processes {
+            active: scanner.active().to_vec(),
+            auto: scanner.auto().cloned(),
+            events,
+        };
+        if updates.send(update).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads the repository every `workspace.interval` seconds, and right away when the chosen process changes directory.
+async fn watch_workspace(
+    config: Arc<Config>,
+    mut cwd: watch::Receiver<Option<String>>,
+    updates: UnboundedSender<Update>,
+) -> () {
+    let mut interval: Interval = interval(config.workspace.interval);
+    let mut last: Option<WorkspaceData> = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            Ok(()) = cwd.changed() => (),
+        }
+        let dir: Option<String> = cwd.borrow_and_update().to_owned();
+        let workspace_config: Arc<Config> = Arc::clone(&config);
+        // Checking for changes stats every tracked file, which takes a while in large repositories
+        let read = move || get_workspace(&workspace_config.workspace, dir.as_deref());
+        let workspace: WorkspaceData = match spawn_blocking(read).await {
+            Err(error) => {
+                warn!("Could not read the workspace: {error}");
+                WorkspaceData::default()
+            }
+            Ok(workspace) => workspace.unwrap_or_default(),
+        };
+        if !publish(&updates, &mut last, workspace, Update::Workspace) {
+            return;
+        }
+    }
+}
+
+/// Reads the shell state files every `shell.interval` seconds.
+async fn watch_shell(
+    config: Arc<Config>,
+    monitor: ShellMonitor,
+    updates: UnboundedSender<Update>,
+) -> () {
+    let mut interval: Interval = interval(config.shell.interval);
+    let mut last: Option<Option<ShellData>> = None;
+    loop {
+        interval.tick().await;
+        if !publish(&updates, &mut last, monitor.current(), Update::Shell) {
+            return;
+        }
+    }
+}
+
+/// Publishes every message `ddrpc run` sends on the daemon socket. Each connection is read by its own task, so a client
+/// that is slow to send its message does not hold up the others.
+async fn watch_daemon(daemon: DaemonListener, updates: UnboundedSender<Update>) -> () {
+    while !updates.is_closed() {
+        let Some(stream) = daemon.accept().await else {
+            continue;
+        };
+        let updates: UnboundedSender<Update> = updates.clone();
+        tokio::spawn(async move {
+            for message in read_messages(stream).await {
+                if updates.send(Update::Daemon(message)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Publishes [`Update::Tick`] every [`TICK`].
+async fn clock(updates: UnboundedSender<Update>) -> () {
+    let mut interval: Interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if updates.send(Update::Tick).is_err() {
+            return;
+        }
+    }
+}
+
+/// Publishes [`Update::Shutdown`] on SIGINT or SIGTERM.
+async fn watch_signals(updates: UnboundedSender<Update>) -> () {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Err(error) => {
+                warn!("Could not listen for SIGTERM: {error}");
+                std::future::pending::<()>().await;
+            }
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => debug!("Received SIGINT"),
+        _ = terminate => debug!("Received SIGTERM"),
+    }
+    let _ = updates.send(Update::Shutdown);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media(title: &str) -> Update {
+        return Update::Media {
+            index: 0,
+            media: Some(MediaData {
+                player: String::from("mpd"),
+                title: title.to_owned(),
+                status: String::from("paused"),
+                ..Default::default()
+            }),
+            variables: HashMap::new(),
+        };
+    }
+
+    fn details(command: DiscordCommand) -> String {
+        let DiscordCommand::Set(data, _) = command else {
+            panic!("Expected an activity");
+        };
+        return data.details;
+    }
+
+    #[test]
+    fn scan_key_ignores_usage_unless_shown() {
+        let process: ProcessData = ProcessData {
+            name: String::from("code"),
+            app_id: None,
+            pid: 42,
+            start_time: 0,
+            run_time: 10,
+            cpu_usage: 1.5,
+            memory: 1024,
+            cwd: String::from("/home/user/project"),
+            exe: String::from("/usr/bin/code"),
+            args: Vec::new(),
+        };
+        let busier: ProcessData = ProcessData {
+            run_time: 13,
+            cpu_usage: 20.0,
+            memory: 2048,
+            ..process.to_owned()
+        };
+        let moved: ProcessData = ProcessData {
+            cwd: String::from("/home/user/other"),
+            ..process.to_owned()
+        };
+
+        let key = |process: &ProcessData, usage: bool| -> ScanKey {
+            return scan_key(&[process.to_owned()], None, Some(process), usage);
+        };
+        assert_eq!(key(&process, false), key(&busier, false));
+        assert_ne!(key(&process, true), key(&busier, true));
+        assert_ne!(key(&process, false), key(&moved, false));
+        assert_ne!(
+            key(&process, false),
+            scan_key(&[process.to_owned()], None, None, false)
+        );
+    }
+
+    #[test]
+    fn publish_skips_unchanged_data() {
+        let (updates, mut receiver) = mpsc::unbounded_channel::<Update>();
+        let mut last: Option<Option<ShellData>> = None;
+
+        assert!(publish(&updates, &mut last, None, Update::Shell));
+        assert!(publish(&updates, &mut last, None, Update::Shell));
+        assert!(matches!(receiver.try_recv(), Ok(Update::Shell(None))));
+        assert!(receiver.try_recv().is_err());
+
+        drop(receiver);
+        assert!(!publish(
+            &updates,
+            &mut last,
+            Some(ShellData::default()),
+            Update::Shell
+        ));
+    }
+
+    #[tokio::test]
+    async fn render_on_changes_until_shutdown() {
+        let mut config: Config = Config::default();
+        config.discord.details = String::from("{{media.title}}");
+        let mut inputs: Inputs = Inputs {
+            media: vec![MediaState::new("mpd")],
+            ..Default::default()
+        };
+        let (updates, mut receiver) = mpsc::unbounded_channel::<Update>();
+        let (discord, mut commands) = mpsc::unbounded_channel::<DiscordCommand>();
+
+        // Updates that arrive together are rendered once, and unchanged activities are not sent again
+        for update in [media("One"), media("Two"), Update::Tick, media("Two")] {
+            updates.send(update).unwrap();
+        }
+        let driver = async {
+            let first: String = details(commands.recv().await.unwrap());
+            updates.send(media("Two")).unwrap();
+            updates.send(Update::Tick).unwrap();
+            updates.send(media("Three")).unwrap();
+            let second: String = details(commands.recv().await.unwrap());
+            updates.send(Update::Shutdown).unwrap();
+            return (first, second);
+        };
+
+        let (result, (first, second)) = tokio::join!(
+            render(&config, &mut inputs, 1, &mut receiver, &discord),
+            driver
+        );
+        result.unwrap();
+        assert_eq!((first.as_str(), second.as_str()), ("Two", "Three"));
+        drop(discord);
+        assert!(commands.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn render_stops_when_discord_fails() {
+        let (updates, mut receiver) = mpsc::unbounded_channel::<Update>();
+        let (discord, _commands) = mpsc::unbounded_channel::<DiscordCommand>();
+        updates
+            .send(Update::DiscordFailed(String::from("Broken pipe")))
+            .unwrap();
+
+        let error: Box<dyn Error> = render(
+            &Config::default(),
+            &mut Inputs::default(),
+            0,
+            &mut receiver,
+            &discord,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Broken pipe");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::prelude::*;
+use std::fs;
+
+/// Application ID of a sandboxed or desktop-launched process, such as `org.blender.Blender` for a Flatpak or `firefox`
+/// for a Snap. The Flatpak instance metadata is used if it is readable, otherwise the ID is taken from the process's
+/// cgroup. Returns `None` for processes that were not started as an application, including container processes.
+pub fn app_id(pid: u32) -> Option<String> {
+    if let Some(app_id) = flatpak_app_id(pid) {
+        return Some(app_id);
+    }
+
+    let cgroup: String = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    let path: &str = cgroup_path(&cgroup)?;
+
+    let app_id: Option<String> = cgroup_app_id(path);
+    trace!("App ID of process {pid} from cgroup {path}: {app_id:?}");
+    return app_id;
+}
+
+/// Path of the systemd cgroup in the contents of `/proc/<pid>/cgroup`.
+fn cgroup_path(cgroup: &str) -> Option<&str> {
+    // cgroup v2 has a single line starting with `0::`, v1 has one line per controller with systemd's one marked `name=systemd`
+    return cgroup.lines().find_map(|line: &str| {
+        line.strip_prefix("0::")
+            .or_else(|| line.split_once(":name=systemd:").map(|(_, path)| path))
+    });
+}
+
+/// Reads the application name from the `.flatpak-info` file Flatpak mounts at the root of the sandbox.
+fn flatpak_app_id(pid: u32) -> Option<String> {
+    let info: String = fs::read_to_string(format!("/proc/{pid}/root/.flatpak-info")).ok()?;
+    let mut in_application: bool = false;
+
+    for line in info.lines() {
+        let line: &str = line.trim();
+        if line.starts_with('[') {
+            in_application = line == "[Application]";
+        } else if in_application {
+            if let Some(name) = line.strip_prefix("name=") {
+                return Some(name.trim().to_owned());
+            }
+        }
+    }
+    return None;
+}
+
+/// Extracts an application ID from the last component of a cgroup path. Recognizes Flatpak
+/// (`app-flatpak-<id>-<n>.scope`), Snap (`snap.<name>.<app>-<uuid>.scope`) and systemd application scopes and services
+/// (`app-<launcher>-<id>-<n>.scope`, `app-<id>@<n>.service`, `app-dbus-:<bus>-<id>@<n>.service`).
+fn cgroup_app_id(path: &str) -> Option<String> {
+    let unit: &str = path.rsplit('/').find(|unit: &&str| !unit.is_empty())?;
+
+    // Containers running systemd have their own application units below the container's cgroup
+    if path.split('/').any(|component: &str| {
+        component.starts_with("docker-")
+            || component.starts_with("libpod-")
+            || component == "docker"
+    }) {
+        trace!("Process is running in a container");
+        return None;
+    }
+
+    if let Some(snap) = unit.strip_prefix("snap.") {
+        return snap.split('.').next().map(str::to_owned);
+    }
+
+    if let Some(flatpak) = unit.strip_prefix("app-flatpak-") {
+        return strip_instance(flatpak.strip_suffix(".scope")?);
+    }
+
+    let app: &str = unit.strip_prefix("app-")?;
+    if let Some(service) = app.strip_suffix(".service") {
+        let service: String = unescape_unit(service.split_once('@').map_or(service, |(id, _)| id));
+        // D-Bus activated services are prefixed with the bus connection that started them
+        return match service
+            .strip_prefix("dbus-:")
+            .and_then(|service: &str| service.split_once('-'))
+        {
+            Some((_, id)) => Some(id.to_owned()),
+            None => Some(service),
+        };
+    }
+
+    let scope: String = strip_instance(app.strip_suffix(".scope")?)?;
+    // Launchers such as `gnome` or `kde` prefix the ID in scopes, application IDs themselves usually contain dots
+    return match scope.split_once('-') {
+        Some((launcher, id))
+            if KNOWN_LAUNCHERS.contains(&launcher)
+                || (!launcher.contains('.') && id.contains('.')) =>
+        {
+            Some(id.to_owned())
+        }
+        _ => Some(scope),
+    };
+}
+
+/// Launchers that prefix application IDs in scope names, which are stripped even if the ID has no dots.
+const KNOWN_LAUNCHERS: [&str; 4] = ["gnome", "kde", "xfce", "cosmic"];
+
+/// Removes the trailing `-<number>` instance suffix from a scope name.
+fn strip_instance(scope: &str) -> Option<String> {
+    let (id, instance) = scope.rsplit_once('-')?;
+    if id.is_empty()
+        || !instance
+            .chars()
+            .all(|char: char| char.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+    return Some(unescape_unit(id));
+}
+
+/// Reverts systemd unit name escaping, where characters such as `-` are written as `\x2d`.
+fn unescape_unit(name: &str) -> String {
+    let mut unescaped: String = String::with_capacity(name.len());
+    let mut rest: &str = name;
+
+    while let Some(index) = rest.find("\\x") {
+        unescaped.push_str(&rest[..index]);
+        match rest
+            .get(index + 2..index + 4)
+            .and_then(|hex: &str| u8::from_str_radix(hex, 16).ok())
+        {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push_str("\\x");
+                rest = &rest[index + 2..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    return unescaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_id_of(cgroup: &str) -> Option<String> {
+        return cgroup_app_id(cgroup_path(cgroup)?);
+    }
+
+    #[test]
+    fn app_id_from_cgroup() {
+        let cases: [(&str, Option<&str>); 13] = [
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-org.blender.Blender-12345.scope\n",
+                Some("org.blender.Blender"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/snap.firefox.firefox-3c8d1a2e-7f5b-4a7e-9d0c-2b1e6f4a8c9d.scope\n",
+                Some("firefox"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-gnome-org.gnome.Nautilus-4242.scope\n",
+                Some("org.gnome.Nautilus"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-gnome-firefox-1234.scope\n",
+                Some("firefox"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-org.kde.konsole@0b9c3e1f.service\n",
+                Some("org.kde.konsole"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-dbus\\x2d:1.2\\x2dorg.gnome.Calculator@1.service\n",
+                Some("org.gnome.Calculator"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-kde-org.kde.dolphin\\x2dbeta-77.scope\n",
+                Some("org.kde.dolphin-beta"),
+            ),
+            (
+                "12:pids:/user.slice/user-1000.slice/session-2.scope\n1:name=systemd:/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-com.spotify.Client-5678.scope\n",
+                Some("com.spotify.Client"),
+            ),
+            (
+                "0::/system.slice/docker-0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef.scope\n",
+                None,
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-0123456789abcdef.scope/container\n",
+                None,
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-0123456789abcdef.scope/container/app.slice/app-org.kde.konsole@1.service\n",
+                None,
+            ),
+            ("0::/init.scope\n", None),
+            ("0::/user.slice/user-1000.slice/session-2.scope\n", None),
+        ];
+
+        for (cgroup, expected) in cases {
+            assert_eq!(app_id_of(cgroup).as_deref(), expected, "{cgroup}");
+        }
+    }
+
+    #[test]
+    fn cgroup_path_prefers_systemd_hierarchy() {
+        assert_eq!(cgroup_path("0::/init.scope\n"), Some("/init.scope"));
+        assert_eq!(
+            cgroup_path("4:memory:/user.slice\n1:name=systemd:/user.slice/session-2.scope\n"),
+            Some("/user.slice/session-2.scope")
+        );
+        assert_eq!(cgroup_path("4:memory:/user.slice\n"), None);
+    }
+
+    #[test]
+    fn unescape_unit_names() {
+        assert_eq!(unescape_unit("org.kde\\x2dtest"), "org.kde-test");
+        assert_eq!(unescape_unit("plain"), "plain");
+        assert_eq!(unescape_unit("trailing\\x"), "trailing\\x");
+        assert_eq!(unescape_unit("bad\\xzz"), "bad\\xzz");
+    }
+}
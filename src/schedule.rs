@@ -0,0 +1,141 @@
+use crate::{parser::variables::template_variables, prelude::*};
+use std::{collections::HashSet, time::Duration};
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// How often time passing is published, for elapsed times. Also the shortest refresh interval of a data source.
+pub const TICK: Duration = Duration::from_secs(1);
+
+/// Data sources the config uses. Found once when the config is loaded, sources nothing uses are never refreshed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Demand {
+    /// `{{auto.*}}` variables. Auto mode only scans for running applications if they are used
+    pub auto: bool,
+    /// `{{media.*}}` or `{{spotify.*}}` variables, `media.timestamps` or `track_changed` hooks
+    pub media: bool,
+    /// `{{process.*}}` or `{{auto.*}}` variables, process hooks, or the workspace of the chosen process
+    pub processes: bool,
+    /// `{{process.cpu}}`, `{{process.memory}}` or `{{process.uptime}}` variables, which change on every process scan
+    pub process_usage: bool,
+    /// `{{shell.*}}` variables
+    pub shell: bool,
+    /// `{{git.*}}` or `{{project.*}}` variables
+    pub workspace: bool,
+}
+
+impl Demand {
+    pub fn new(config: &Config) -> Self {
+        let variables: HashSet<String> = template_variables(config);
+        let uses = |namespaces: &[&str]| -> bool {
+            variables.iter().any(|variable: &String| {
+                namespaces
+                    .iter()
+                    .any(|namespace: &&str| variable.starts_with(&format!("{namespace}.")))
+            })
+        };
+
+        let workspace: bool = uses(&["git", "project"]);
+        let demand: Demand = Self {
+            auto: uses(&["auto"]),
+            media: uses(&["media", "spotify"])
+                || config.media.timestamps
+                || config
+                    .hooks
+                    .iter()
+                    .any(|hook: &HookConfig| hook.track_changed),
+            processes: uses(&["process", "auto"])
+                || (workspace && config.workspace.path.is_empty())
+                || config.hooks.iter().any(|hook: &HookConfig| {
+                    hook.process_started.is_some() || hook.process_exited.is_some()
+                }),
+            process_usage: ["process.cpu", "process.memory", "process.uptime"]
+                .iter()
+                .any(|variable: &&str| variables.contains(*variable)),
+            shell: uses(&["shell"]),
+            workspace,
+        };
+        debug!("Data sources used by the config: {demand:?}");
+        return demand;
+    }
+}
+
+/// Timer for a data source refreshed every `seconds`, but no more often than every [`TICK`]. The first tick completes
+/// right away.
+pub fn interval(seconds: u64) -> Interval {
+    let mut interval: Interval = tokio::time::interval(Duration::from_secs(seconds).max(TICK));
+    // A refresh that takes longer than the interval does not cause a burst of refreshes after it
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    return interval;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demand(details: &str, state: &str) -> Demand {
+        let mut config: Config = Config::default();
+        config.discord.details = details.to_owned();
+        config.discord.state = state.to_owned();
+        return Demand::new(&config);
+    }
+
+    #[test]
+    fn demand_from_templates() {
+        assert_eq!(demand("Coding", ""), Demand::default());
+        assert_eq!(
+            demand("{{spotify.track.name}}", ""),
+            Demand {
+                media: true,
+                ..Demand::default()
+            }
+        );
+        assert_eq!(
+            demand("{{process.name}}", "{{shell.command}}"),
+            Demand {
+                processes: true,
+                shell: true,
+                ..Demand::default()
+            }
+        );
+        assert_eq!(
+            demand("{{process.name}}", "{{process.cpu}}"),
+            Demand {
+                processes: true,
+                process_usage: true,
+                ..Demand::default()
+            }
+        );
+        assert_eq!(
+            demand("{{auto.app.name}}", ""),
+            Demand {
+                auto: true,
+                processes: true,
+                ..Demand::default()
+            }
+        );
+        // The workspace is found from the chosen process unless a path is set
+        assert_eq!(
+            demand("{{git.branch}}", ""),
+            Demand {
+                processes: true,
+                workspace: true,
+                ..Demand::default()
+            }
+        );
+        // Only whole namespaces count
+        assert_eq!(
+            demand("{{mediaplayer}} {{spotifyish.name}}", ""),
+            Demand::default()
+        );
+    }
+
+    #[test]
+    fn demand_from_settings() {
+        let mut config: Config = Config::default();
+        config.workspace.path = String::from("/tmp");
+        config.discord.details = String::from("{{project.name}}");
+        assert!(!Demand::new(&config).processes);
+
+        config.media.timestamps = true;
+        assert!(Demand::new(&config).media);
+    }
+}
@@ -1,168 +1,963 @@
-use crate::prelude::*;
+use crate::{
+    credentials,
+    media::{MediaData, MediaProvider},
+    parser::{variables::template_variables, CliSpotifyClient},
+    prelude::*,
+    processes::format_duration,
+    task::unix_time,
+};
+use async_trait::async_trait;
 use rspotify::{
     clients::{BaseClient, OAuthClient},
-    model::{AdditionalType, PlayableItem},
+    http::{HttpClient, HttpError},
+    model::{
+        AdditionalType, AlbumId, ArtistId, Context, Image, PlayableItem, PlaylistId, PrivateUser,
+        ShowId, SimplifiedArtist, Type,
+    },
+    prelude::Id,
     scopes,
     sync::Mutex,
-    AuthCodeSpotify, Credentials, OAuth, Token,
+    AuthCodePkceSpotify, AuthCodeSpotify, CallbackError, ClientError, ClientResult, Credentials,
+    OAuth, Token, TokenCallback,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    runtime::Handle,
+    task::block_in_place,
+    time::timeout,
 };
-use std::sync::Arc;
 
+/// Scope of the currently playing endpoint, enough for the track, episode and progress variables
+const CURRENTLY_PLAYING_SCOPE: &str = "user-read-currently-playing";
+/// Variables only available from the playback state endpoint, which needs the `user-read-playback-state` scope
+const PLAYBACK_STATE_VARIABLES: [&str; 5] = [
+    "spotify.context.",
+    "spotify.device.",
+    "spotify.repeat",
+    "spotify.shuffle",
+    "spotify.volume",
+];
+/// Wait after the first failed request. Doubles with each failure after it
+const BACKOFF_MIN: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// Wait between checks for a new refresh token after Spotify rejected the saved one
+const UNAUTHORIZED_RETRY: Duration = Duration::from_secs(60);
+/// Context names kept at most. The cache is emptied when it is full
+const CONTEXT_NAMES_MAX: usize = 100;
+/// Time to wait for the user to authorize ddrpc in the browser
+const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+/// Artists with their own `spotify.track.artist.<n>.*` variables
+const INDEXED_ARTISTS: usize = 4;
+
+/// Scopes needed for the `spotify.*` variables used in templates. Scopes for the playback state and playlist names are
+/// only requested if templates show them.
+pub fn required_scopes(variables: &HashSet<String>) -> HashSet<String> {
+    let mut scopes: HashSet<String> = scopes!(CURRENTLY_PLAYING_SCOPE);
+    if variables
+        .iter()
+        .any(|variable: &String| is_playback_state_variable(variable))
+    {
+        scopes.insert(String::from("user-read-playback-state"));
+    }
+    // Names of private and collaborative playlists can only be read with these
+    if variables.contains("spotify.context.name") {
+        scopes.extend(scopes!("playlist-read-private playlist-read-collaborative"));
+    }
+    return scopes;
+}
+
+/// Whether `variable` comes from the playback state endpoint rather than the currently playing item.
+pub fn is_playback_state_variable(variable: &str) -> bool {
+    return PLAYBACK_STATE_VARIABLES
+        .iter()
+        .any(|prefix: &&str| variable.starts_with(prefix));
+}
+
+/// Creates the Spotify client and authorizes it if there is no refresh token, or the saved one lacks `scopes`. Apps
+/// without a client secret use PKCE. The secrets in `config` have to be loaded from the credentials store first.
+/// Returns `None` if Spotify is not set up or authorization failed.
 #[instrument(skip_all)]
 pub async fn client_init(
     config: &mut SpotifyConfig,
-) -> Result<Option<AuthCodeSpotify>, Box<dyn Error>> {
-    if config.client_id.is_empty() || config.client_secret.is_empty() {
-        trace!("No Spotify client ID and/or secret available");
+    credentials_config: &CredentialsConfig,
+    scopes: HashSet<String>,
+    no_browser: bool,
+) -> Result<Option<SpotifyClient>, Box<dyn Error>> {
+    if config.client_id.is_empty() {
+        trace!("No Spotify client ID available");
         warn!("Skipping Spotify authorization. Spotify fields will use fallback values.");
         return Ok(None);
     }
-    let credentials: Credentials = Credentials::new(&config.client_id, &config.client_secret);
-    let oauth: OAuth = OAuth {
-        redirect_uri: "http://localhost:3000/callback".to_string(),
-        state: "ddrpcscope".to_string(),
-        scopes: scopes!("user-read-currently-playing"),
-        proxies: None,
+    let callback_credentials: CredentialsConfig = credentials_config.to_owned();
+    let client_config: rspotify::Config = rspotify::Config {
+        token_callback_fn: Arc::new(Some(TokenCallback(Box::new(move |token: Token| {
+            persist_refresh_token(&callback_credentials, token)
+        })))),
+        ..Default::default()
     };
-    let mut client = AuthCodeSpotify::new(credentials, oauth);
-    trace!("Spotify client initialized");
+    let mut client: SpotifyClient = new_client(config, scopes, client_config);
 
-    match authorize(config, &mut client).await {
-        Err(_) => return Ok(None),
-        _ => (),
+    if authorize(config, &mut client, no_browser).await.is_err() {
+        return Ok(None);
     }
 
-    save_refresh_token(config, &client).await?;
+    save_refresh_token(config, credentials_config, &client).await?;
 
     return Ok(Some(client));
 }
 
+/// Creates the client for the app in `config`. Apps without a client secret use PKCE.
+fn new_client(
+    config: &SpotifyConfig,
+    scopes: HashSet<String>,
+    client_config: rspotify::Config,
+) -> SpotifyClient {
+    let oauth: OAuth = OAuth {
+        redirect_uri: config.redirect_uri.to_owned(),
+        scopes,
+        ..Default::default()
+    };
+    let client: SpotifyClient = match config.client_secret.is_empty() {
+        true => SpotifyClient::Pkce(AuthCodePkceSpotify::with_config(
+            Credentials::new_pkce(&config.client_id),
+            oauth,
+            client_config,
+        )),
+        false => SpotifyClient::AuthCode(AuthCodeSpotify::with_config(
+            Credentials::new(&config.client_id, &config.client_secret),
+            oauth,
+            client_config,
+        )),
+    };
+    trace!("Spotify client initialized");
+    return client;
+}
+
+/// Saves refresh tokens that change when the access token is refreshed. PKCE refresh tokens are replaced on every
+/// refresh, so the saved one would stop working otherwise.
+fn persist_refresh_token(
+    credentials_config: &CredentialsConfig,
+    token: Token,
+) -> Result<(), CallbackError> {
+    let Some(refresh_token) = token.refresh_token else {
+        return Ok(());
+    };
+    let save = async {
+        let mut config: Config = read_config_file(false)?;
+        credentials::load(&mut config).await?;
+        config.spotify.refresh_token = refresh_token;
+        return credentials::save(credentials_config, &config.spotify).await;
+    };
+
+    // Called from inside the runtime, which has to be left to wait for the credentials store
+    return block_in_place(|| Handle::current().block_on(save))
+        .map_err(|error: Box<dyn Error>| CallbackError::CustomizedError(error.to_string()));
+}
+
 /// Has a blank result so that [`client_init`] can know to return a `None` instead of crashing the program
 #[instrument(skip_all)]
-async fn authorize(config: &SpotifyConfig, client: &mut AuthCodeSpotify) -> Result<(), ()> {
-    if config.refresh_token.is_empty() {
-        trace!("No refresh token found, requesting authorization");
-        let url: String = match client.get_authorize_url(false) {
-            Err(error) => {
-                error!("{error}");
-                warn!("Skipping Spotify authorization. Spotify fields will use fallback values.");
-                return Err(());
-            }
-            Ok(url) => url,
-        };
+async fn authorize(
+    config: &SpotifyConfig,
+    client: &mut SpotifyClient,
+    no_browser: bool,
+) -> Result<(), ()> {
+    let granted: HashSet<String> = granted_scopes(config);
+    let missing: Vec<&String> = client.get_oauth().scopes.difference(&granted).collect();
+
+    if config.refresh_token.is_empty() || !missing.is_empty() {
+        match config.refresh_token.is_empty() {
+            true => trace!("No refresh token found, requesting authorization"),
+            false => info!("Templates use Spotify data that needs the {missing:?} scopes, requesting authorization"),
+        }
 
-        return match client.prompt_for_token(&url).await {
+        return match request_authorization(config, client, no_browser).await {
             Err(error) => {
-                error!("Error: {error}");
+                error!("Spotify authorization failed: {error}");
                 warn!("Skipping Spotify authorization. Spotify fields will use fallback values.");
                 Err(())
             }
-            _ => {
+            Ok(()) => {
                 debug!("Spotify client successfully authenticated");
                 Ok(())
             }
         };
     }
 
-    let mut token: Token = Token::default();
-    token.refresh_token = Some(config.refresh_token.to_owned());
-    token.scopes = scopes!("user-read-currently-playing");
+    restore_token(config, client).await;
+    Ok(())
+}
 
-    trace!("Created {token:?} from refresh token");
+/// Gives the client a token with the saved refresh token, which is exchanged for an access token on the first request.
+async fn restore_token(config: &SpotifyConfig, client: &SpotifyClient) -> () {
+    let token: Token = Token {
+        refresh_token: Some(config.refresh_token.to_owned()),
+        scopes: granted_scopes(config),
+        ..Default::default()
+    };
 
-    client.token = Arc::new(Mutex::new(Some(token)));
+    trace!("Created a token from the saved refresh token");
 
-    Ok(())
+    *client
+        .get_token()
+        .lock()
+        .await
+        .expect("Token mutex poisoned") = Some(token);
+}
+
+/// Scopes the saved refresh token was granted. Refresh tokens from before scopes were saved only have the currently
+/// playing scope.
+fn granted_scopes(config: &SpotifyConfig) -> HashSet<String> {
+    return match config.scopes.is_empty() {
+        true => scopes!(CURRENTLY_PLAYING_SCOPE),
+        false => config.scopes.iter().cloned().collect(),
+    };
+}
+
+/// Opens the authorization page and exchanges the code Spotify redirects back with for a token. The redirect is caught
+/// by a local listener on the redirect URI's port. If the browser runs on another machine, the URL it was redirected to
+/// can be pasted instead.
+async fn request_authorization(
+    config: &SpotifyConfig,
+    client: &mut SpotifyClient,
+    no_browser: bool,
+) -> Result<(), Box<dyn Error>> {
+    let url: String = match client {
+        SpotifyClient::AuthCode(client) => client.get_authorize_url(false)?,
+        SpotifyClient::Pkce(client) => client.get_authorize_url(None)?,
+    };
+    let (port, path) = redirect_address(config)?;
+
+    let listener: Option<TcpListener> = match TcpListener::bind(("127.0.0.1", port)).await {
+        Err(error) => {
+            warn!("Could not listen for the Spotify redirect on port {port}: {error}");
+            None
+        }
+        Ok(listener) => {
+            debug!("Listening for the Spotify redirect on port {port}");
+            Some(listener)
+        }
+    };
+
+    if no_browser {
+        println!("Open this URL to authorize ddrpc with Spotify:\n\n{url}\n");
+    } else if let Err(error) = webbrowser::open(&url) {
+        debug!("Could not open a browser: {error}");
+        println!("Open this URL to authorize ddrpc with Spotify:\n\n{url}\n");
+    } else {
+        println!("Opened Spotify authorization in your browser. If it did not open, use this URL:\n\n{url}\n");
+    }
+    println!("If the browser cannot reach this machine, paste the URL it was redirected to here:");
+
+    let redirect: String = timeout(AUTHORIZATION_TIMEOUT, wait_for_redirect(listener, &path))
+        .await
+        .map_err(|_| "Timed out waiting for authorization")??;
+    let code: String = client
+        .parse_response_code(&redirect)
+        .ok_or("The redirect URL has no authorization code or a different state")?;
+
+    client.request_token(&code).await?;
+    return Ok(());
+}
+
+/// Port to listen on and path of `SpotifyConfig::redirect_uri`. The port is `SpotifyConfig::redirect_port` if set, so
+/// the listener can sit behind a forwarded or proxied port.
+fn redirect_address(config: &SpotifyConfig) -> Result<(u16, String), Box<dyn Error>> {
+    let Some(address) = config.redirect_uri.strip_prefix("http://") else {
+        return Err(format!(
+            "Redirect URI \"{}\" must start with http:// to be caught locally",
+            config.redirect_uri
+        )
+        .into());
+    };
+    let (authority, path) = match address.find('/') {
+        None => (address, "/"),
+        Some(index) => address.split_at(index),
+    };
+    let uri_port: Option<u16> = match authority.rsplit_once(':') {
+        None => None,
+        Some((_, port)) => Some(port.parse::<u16>()?),
+    };
+
+    return Ok((
+        config.redirect_port.or(uri_port).unwrap_or(80),
+        path.to_owned(),
+    ));
+}
+
+/// Waits for a request to `path` on the listener or a URL on stdin, and returns the redirected URL.
+async fn wait_for_redirect(
+    listener: Option<TcpListener>,
+    path: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open: bool = true;
+
+    loop {
+        tokio::select! {
+            connection = async { listener.as_ref().unwrap().accept().await }, if listener.is_some() => {
+                let (stream, _) = connection?;
+                if let Some(redirect) = handle_callback(stream, path).await? {
+                    return Ok(redirect);
+                }
+            }
+            line = stdin.next_line(), if stdin_open => match line? {
+                None => stdin_open = false,
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => return Ok(line.trim().to_owned()),
+            },
+            else => return Err("No way to receive the Spotify redirect".into()),
+        }
+    }
+}
+
+/// Answers one HTTP request to the listener. Returns the redirected URL if the request was the redirect from Spotify.
+async fn handle_callback(stream: TcpStream, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut stream: BufReader<TcpStream> = BufReader::new(stream);
+    let mut request_line: String = String::new();
+    stream.read_line(&mut request_line).await?;
+
+    // Request line: `GET /callback?code=...&state=... HTTP/1.1`
+    let target: &str = request_line.split(' ').nth(1).unwrap_or_default();
+    let (request_path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params: HashMap<&str, &str> = query
+        .split('&')
+        .filter_map(|param: &str| param.split_once('='))
+        .collect();
+
+    let (status, body, result) = if request_path != path {
+        ("404 Not Found", "Not found", Ok(None))
+    } else if let Some(error) = params.get("error") {
+        (
+            "200 OK",
+            "Spotify authorization was denied. You can close this tab.",
+            Err(format!("Spotify returned \"{error}\"").into()),
+        )
+    } else if params.contains_key("code") {
+        (
+            "200 OK",
+            "ddrpc is authorized with Spotify. You can close this tab.",
+            Ok(Some(format!("http://127.0.0.1{target}"))),
+        )
+    } else {
+        ("400 Bad Request", "Missing authorization code", Ok(None))
+    };
+
+    stream
+        .get_mut()
+        .write_all(
+            format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    return result;
+}
+
+/// Spotify Web API client. Apps with a client secret use the authorization code flow, apps without one use PKCE.
+/// Implements the rspotify client traits by forwarding to the client in use.
+#[derive(Clone, Debug)]
+pub enum SpotifyClient {
+    AuthCode(AuthCodeSpotify),
+    Pkce(AuthCodePkceSpotify),
+}
+
+impl Default for SpotifyClient {
+    fn default() -> Self {
+        return Self::AuthCode(AuthCodeSpotify::default());
+    }
+}
+
+#[async_trait]
+impl BaseClient for SpotifyClient {
+    fn get_config(&self) -> &rspotify::Config {
+        return match self {
+            Self::AuthCode(client) => client.get_config(),
+            Self::Pkce(client) => client.get_config(),
+        };
+    }
+
+    fn get_http(&self) -> &HttpClient {
+        return match self {
+            Self::AuthCode(client) => client.get_http(),
+            Self::Pkce(client) => client.get_http(),
+        };
+    }
+
+    fn get_creds(&self) -> &Credentials {
+        return match self {
+            Self::AuthCode(client) => client.get_creds(),
+            Self::Pkce(client) => client.get_creds(),
+        };
+    }
+
+    fn get_token(&self) -> Arc<Mutex<Option<Token>>> {
+        return match self {
+            Self::AuthCode(client) => client.get_token(),
+            Self::Pkce(client) => client.get_token(),
+        };
+    }
+
+    async fn refetch_token(&self) -> ClientResult<Option<Token>> {
+        return match self {
+            Self::AuthCode(client) => client.refetch_token().await,
+            Self::Pkce(client) => client.refetch_token().await,
+        };
+    }
+}
+
+#[async_trait]
+impl OAuthClient for SpotifyClient {
+    fn get_oauth(&self) -> &OAuth {
+        return match self {
+            Self::AuthCode(client) => client.get_oauth(),
+            Self::Pkce(client) => client.get_oauth(),
+        };
+    }
+
+    async fn request_token(&self, code: &str) -> ClientResult<()> {
+        return match self {
+            Self::AuthCode(client) => client.request_token(code).await,
+            Self::Pkce(client) => client.request_token(code).await,
+        };
+    }
 }
 
+/// Saves the refresh token to the credentials store and the scopes it was granted to the config.
 #[instrument(skip_all)]
 async fn save_refresh_token(
     config: &mut SpotifyConfig,
-    client: &AuthCodeSpotify,
+    credentials_config: &CredentialsConfig,
+    client: &SpotifyClient,
 ) -> Result<(), Box<dyn Error>> {
     trace!("Attempting to extract token");
 
     let token_mutex: Arc<Mutex<Option<Token>>> = client.get_token();
 
-    let refresh_token = token_mutex.lock().await.expect("Token mutex poisoned");
-    let refresh_token: &String = refresh_token
-        .as_ref()
-        .expect("Token field should always be available at this point")
+    let token = token_mutex.lock().await.expect("Token mutex poisoned");
+    let token: &Token = token.as_ref().ok_or("Spotify did not return a token")?;
+    let refresh_token: &String = token
         .refresh_token
         .as_ref()
-        .expect("Refresh token should always be available if this function is called");
+        .ok_or("Spotify did not return a refresh token")?;
 
-    trace!("Spotify refresh token: {:?}", refresh_token);
     config.refresh_token = refresh_token.to_owned();
+    config.scopes = token.scopes.iter().cloned().collect();
+    config.scopes.sort();
 
+    credentials::save(credentials_config, config).await?;
     return write_config(config);
+}
+
+/// Sets the Spotify app credentials and saves them. The saved tokens are removed if the client ID changes or the
+/// secret is added or removed, as refresh tokens only work with the app and flow they were granted to.
+#[instrument(skip_all)]
+pub async fn set_spotify_client(
+    config: &mut SpotifyConfig,
+    credentials_config: &CredentialsConfig,
+    args: CliSpotifyClient,
+) -> Result<(), Box<dyn Error>> {
+    if args.id.is_none() && args.secret.is_none() {
+        let id: &str = match config.client_id.is_empty() {
+            true => "<None>",
+            false => &config.client_id,
+        };
+        let secret: &str = match config.client_secret.is_empty() {
+            true => "<None> (PKCE)",
+            false => "<Set>",
+        };
+        println!("Client ID: {id}\nClient Secret: {secret}");
+        return Ok(());
+    }
+
+    let id_changed: bool = args
+        .id
+        .as_ref()
+        .is_some_and(|id: &String| *id != config.client_id);
+    let flow_changed: bool = args
+        .secret
+        .as_ref()
+        .is_some_and(|secret: &String| secret.is_empty() != config.client_secret.is_empty());
+    if (id_changed || flow_changed) && !config.refresh_token.is_empty() {
+        info!("Removing the saved Spotify tokens, as they belong to the previous client");
+        config.refresh_token = String::new();
+        config.scopes = Vec::new();
+    }
+
+    if let Some(id) = args.id {
+        config.client_id = id;
+    }
+    if let Some(secret) = args.secret {
+        config.client_secret = secret;
+    }
+    credentials::save(credentials_config, config).await?;
+    write_config(config)?;
 
-    // return match token_mutex
-    //     .lock()
-    //     .await
-    //     .expect("Token mutex poisoned")
-    //     .as_mut()
-    // {
-    //     None => unreachable!("Token field should always be available if this function is called"),
-    //     Some(token) => match &token.refresh_token {
-    //         None => {
-    //             unreachable!("Refresh token should always be available if this function is called")
-    //         }
-    //         Some(refresh_token) => {
-    //             trace!("Spotify refresh token: {refresh_token:?}");
-    //             config.refresh_token = refresh_token.to_owned();
-
-    //             write_config(config)
-    //         }
-    //     },
-    // };
+    println!("Saved the Spotify client. Run `ddrpc spotify login` to authorize it");
+    return Ok(());
 }
 
+/// Authorizes ddrpc with the scopes the templates need and saves the refresh token, replacing the saved one. The saved
+/// token is kept if authorization fails.
 #[instrument(skip_all)]
-pub async fn get_currently_playing_track(
-    client: &AuthCodeSpotify,
-) -> Result<Option<TrackData>, ()> {
-    match client
-        .current_playing(None, Some(&[AdditionalType::Track]))
-        .await
-    {
+pub async fn spotify_login(config: &mut Config, no_browser: bool) -> Result<(), Box<dyn Error>> {
+    if config.spotify.client_id.is_empty() {
+        return Err(
+            "No Spotify client ID set. Set one with `ddrpc spotify client --id <ID>`".into(),
+        );
+    }
+
+    let scopes: HashSet<String> = required_scopes(&template_variables(config));
+    config.spotify.refresh_token = String::new();
+    let Some(client) =
+        client_init(&mut config.spotify, &config.credentials, scopes, no_browser).await?
+    else {
+        return Err("Spotify authorization failed".into());
+    };
+
+    let user: PrivateUser = client.me().await?;
+    println!("Logged in to Spotify as {}", user_name(&user));
+    return Ok(());
+}
+
+/// Removes the saved Spotify tokens. Spotify has no way to revoke them, so access has to be removed in the account
+/// settings to invalidate them.
+#[instrument(skip_all)]
+pub async fn spotify_logout(
+    config: &mut SpotifyConfig,
+    credentials_config: &CredentialsConfig,
+) -> Result<(), Box<dyn Error>> {
+    if config.refresh_token.is_empty() {
+        println!("Not logged in to Spotify");
+        return Ok(());
+    }
+
+    config.refresh_token = String::new();
+    config.scopes = Vec::new();
+    credentials::save(credentials_config, config).await?;
+    write_config(config)?;
+
+    println!("Removed the saved Spotify tokens. To revoke access completely, remove the app at https://www.spotify.com/account/apps/");
+    return Ok(());
+}
+
+/// Prints the account, the granted scopes, when the access token expires and what is playing. Only reads: it never
+/// authorizes, not even if templates need more scopes, and does not save the tokens it refreshes.
+#[instrument(skip_all)]
+pub async fn print_spotify_status(config: &Config) -> Result<(), Box<dyn Error>> {
+    let id: &str = match config.spotify.client_id.is_empty() {
+        true => "<None>",
+        false => &config.spotify.client_id,
+    };
+    let flow: &str = match config.spotify.client_secret.is_empty() {
+        true => "PKCE",
+        false => "Client secret",
+    };
+    println!("Client ID: {id}\nAuthorization: {flow}");
+    if config.spotify.client_id.is_empty() || config.spotify.refresh_token.is_empty() {
+        println!("Not logged in. Run `ddrpc spotify login` to authorize ddrpc");
+        return Ok(());
+    }
+
+    let client: SpotifyClient = new_client(
+        &config.spotify,
+        granted_scopes(&config.spotify),
+        rspotify::Config::default(),
+    );
+    restore_token(&config.spotify, &client).await;
+
+    // Refreshing the access token first also makes the expiry and scopes below current
+    let user: ClientResult<PrivateUser> = match client.auto_reauth().await {
+        Err(error) => Err(error),
+        Ok(()) => client.me().await,
+    };
+    let user: PrivateUser = match user {
         Err(error) => {
-            error!("Error: {error}");
-            return Err(());
-        }
-        Ok(context) => match context {
-            Some(context) if context.is_playing => {
-                if let Some(PlayableItem::Track(track)) = context.item {
-                    let mut artists: String = String::new();
-                    for (index, artist) in track.artists.iter().enumerate() {
-                        if index == 0 {
-                            artists.push_str(&artist.name);
-                        } else {
-                            artists.push_str(&format!(", {}", artist.name))
-                        }
-                    }
-
-                    let track_url: String = match track.external_urls.get_key_value("spotify") {
-                        None => unreachable!("Track URL should always be available at this point"),
-                        Some(url) => url.1.to_owned(),
-                    };
-
-                    return Ok(Some(TrackData {
-                        album_cover_url: track.album.images[0].url.to_owned(),
-                        album_name: track.album.name,
-                        artists,
-                        name: track.name,
-                        track_url,
-                    }));
+            return match RequestError::new(&error) {
+                RequestError::Unauthorized => {
+                    println!("Status: {}\nSpotify rejected the refresh token, it was revoked or has expired. Run `ddrpc spotify login` to authorize ddrpc again", SpotifyStatus::Unauthorized);
+                    Ok(())
+                }
+                RequestError::RateLimited(_) => {
+                    println!("Status: {}", SpotifyStatus::RateLimited);
+                    Ok(())
+                }
+                RequestError::Other => Err(error.into()),
+            };
+        }
+        Ok(user) => user,
+    };
+    let token: Token = client
+        .get_token()
+        .lock()
+        .await
+        .expect("Token mutex poisoned")
+        .clone()
+        .ok_or("No Spotify access token")?;
+
+    let mut scopes: Vec<&str> = token.scopes.iter().map(String::as_str).collect();
+    scopes.sort();
+    let expires: String = match token.expires_at {
+        None => String::from("<Unknown>"),
+        Some(expires_at) => format!(
+            "in {} minutes",
+            (expires_at.timestamp() - unix_time() as i64).max(0) / 60
+        ),
+    };
+    let playing: String = match get_currently_playing_item(&client, false).await {
+        Err(error) => format!("<Unavailable: {error}>"),
+        Ok(None) => String::from("<Nothing>"),
+        Ok(Some(playback)) => {
+            let (name, by) = match &playback.item {
+                PlayingItem::Track(track) => (&track.name, format!("by {}", track.artists)),
+                PlayingItem::Episode(episode) => {
+                    (&episode.name, format!("from {}", episode.show_name))
                 }
-                trace!("No track playing");
+            };
+            format!(
+                "{name} {by} ({} / {}{})",
+                format_duration(playback.progress / 1000),
+                format_duration(playback.item.duration() / 1000),
+                match playback.is_playing {
+                    true => "",
+                    false => ", paused",
+                },
+            )
+        }
+    };
+
+    println!(
+        "Status: {}\nAccount: {}\nScopes: {}\nAccess Token Expires: {expires}\nPlaying: {playing}",
+        SpotifyStatus::Ok,
+        user_name(&user),
+        scopes.join(", "),
+    );
+
+    let mut missing: Vec<String> = required_scopes(&template_variables(config))
+        .difference(&token.scopes)
+        .cloned()
+        .collect();
+    missing.sort();
+    if !missing.is_empty() {
+        println!(
+            "\nTemplates use data that needs the {} scopes. Run `ddrpc spotify login` to grant them",
+            missing.join(", ")
+        );
+    }
+    return Ok(());
+}
+
+/// Display name and ID of a Spotify user, or only the ID if there is no display name.
+fn user_name(user: &PrivateUser) -> String {
+    return match &user.display_name {
+        None => user.id.id().to_owned(),
+        Some(name) => format!("{name} ({})", user.id.id()),
+    };
+}
+
+/// The current track or episode. With `playback_state`, it is read from the playback state endpoint, which also gives
+/// the device, context, shuffle, repeat and volume, but needs the `user-read-playback-state` scope.
+#[instrument(skip_all)]
+pub async fn get_currently_playing_item(
+    client: &SpotifyClient,
+    playback_state: bool,
+) -> ClientResult<Option<Playback>> {
+    // rspotify panics if refreshing the access token fails during a request, so refresh it beforehand
+    client.auto_reauth().await?;
+    let additional_types: [AdditionalType; 2] = [AdditionalType::Track, AdditionalType::Episode];
+
+    let (item, is_playing, progress, state) = match playback_state {
+        false => match client
+            .current_playing(None, Some(&additional_types))
+            .await?
+        {
+            None => {
+                trace!("No track detected");
                 return Ok(None);
             }
-            _ => {
+            Some(context) => (context.item, context.is_playing, context.progress, None),
+        },
+        true => match client
+            .current_playback(None, Some(&additional_types))
+            .await?
+        {
+            None => {
                 trace!("No track detected");
                 return Ok(None);
             }
+            Some(context) => {
+                let state: PlaybackState = PlaybackState {
+                    context: context.context.map(ContextData::new),
+                    device_name: context.device.name,
+                    device_type: <&str>::from(context.device._type).to_owned(),
+                    repeat: <&str>::from(context.repeat_state).to_owned(),
+                    shuffle: context.shuffle_state,
+                    volume: context.device.volume_percent,
+                };
+                (
+                    context.item,
+                    context.is_playing,
+                    context.progress,
+                    Some(state),
+                )
+            }
         },
+    };
+
+    let item: PlayingItem = match item {
+        Some(PlayableItem::Track(track)) => {
+            let artists: String = track
+                .artists
+                .iter()
+                .map(|artist| artist.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ");
+
+            PlayingItem::Track(TrackData {
+                album_cover_url: first_image(&track.album.images),
+                album_name: track.album.name,
+                // Release dates are `YYYY`, `YYYY-MM` or `YYYY-MM-DD` depending on their precision
+                album_year: track
+                    .album
+                    .release_date
+                    .map(|date: String| date.chars().take(4).collect())
+                    .unwrap_or_default(),
+                artist_urls: track
+                    .artists
+                    .iter()
+                    .map(|artist: &SimplifiedArtist| {
+                        (artist.name.to_owned(), spotify_url(&artist.external_urls))
+                    })
+                    .collect(),
+                artists,
+                disc_number: track.disc_number,
+                duration: track.duration.num_milliseconds() as u64,
+                explicit: track.explicit,
+                name: track.name,
+                popularity: track.popularity,
+                track_number: track.track_number,
+                track_url: spotify_url(&track.external_urls),
+            })
+        }
+        Some(PlayableItem::Episode(episode)) => {
+            // Episodes usually share the show's artwork, but not always
+            let image_url: String = match episode.images.is_empty() {
+                true => first_image(&episode.show.images),
+                false => first_image(&episode.images),
+            };
+
+            PlayingItem::Episode(EpisodeData {
+                duration: episode.duration.num_milliseconds() as u64,
+                image_url,
+                name: episode.name,
+                show_name: episode.show.name,
+                show_publisher: episode.show.publisher,
+                url: spotify_url(&episode.external_urls),
+            })
+        }
+        None => {
+            trace!("Nothing playing");
+            return Ok(None);
+        }
+    };
+
+    return Ok(Some(Playback {
+        item,
+        is_playing,
+        progress: progress.map_or(0, |progress| progress.num_milliseconds() as u64),
+        state,
+    }));
+}
+
+fn first_image(images: &[Image]) -> String {
+    return images
+        .first()
+        .map(|image: &Image| image.url.to_owned())
+        .unwrap_or_default();
+}
+
+fn spotify_url(external_urls: &HashMap<String, String>) -> String {
+    return external_urls.get("spotify").cloned().unwrap_or_default();
+}
+
+/// Playback state returned by [`get_currently_playing_item`]. Paused playback keeps its item.
+#[derive(Debug)]
+pub struct Playback {
+    pub item: PlayingItem,
+    pub is_playing: bool,
+    /// Milliseconds
+    pub progress: u64,
+    /// Only read if templates use it
+    pub state: Option<PlaybackState>,
+}
+
+impl Playback {
+    /// The `spotify.*` template variables of the item, its progress and the playback state.
+    pub fn variables(&self) -> HashMap<String, String> {
+        let duration: u64 = self.item.duration();
+        let mut variables: HashMap<String, String> = self.item.variables();
+        variables.extend([
+            (
+                String::from("spotify.progress"),
+                format_duration(self.progress / 1000),
+            ),
+            (
+                String::from("spotify.progress_ms"),
+                self.progress.to_string(),
+            ),
+            (
+                String::from("spotify.duration"),
+                format_duration(duration / 1000),
+            ),
+            (String::from("spotify.duration_ms"), duration.to_string()),
+            (
+                String::from("spotify.is_playing"),
+                self.is_playing.to_string(),
+            ),
+        ]);
+        if let Some(state) = &self.state {
+            variables.extend(state.variables());
+        }
+        return variables;
+    }
+
+    fn media_data(self) -> MediaData {
+        let variables: HashMap<String, String> = self.variables();
+        let length: u64 = self.item.duration();
+        let (title, artists, album, art_url, url) = match self.item {
+            PlayingItem::Track(track) => (
+                track.name,
+                track.artists,
+                track.album_name,
+                track.album_cover_url,
+                track.track_url,
+            ),
+            PlayingItem::Episode(episode) => (
+                episode.name,
+                episode.show_publisher,
+                episode.show_name,
+                episode.image_url,
+                episode.url,
+            ),
+        };
+
+        return MediaData {
+            player: String::from("spotify"),
+            title,
+            artists,
+            album,
+            art_url,
+            url,
+            status: String::from(match self.is_playing {
+                true => "playing",
+                false => "paused",
+            }),
+            position: self.progress,
+            length,
+            variables,
+            ..Default::default()
+        };
+    }
+}
+
+/// Device, context and playback settings from the playback state endpoint.
+#[derive(Debug, Default)]
+pub struct PlaybackState {
+    pub context: Option<ContextData>,
+    pub device_name: String,
+    /// Such as `computer`, `smartphone` or `speaker`
+    pub device_type: String,
+    /// `off`, `track` or `context`
+    pub repeat: String,
+    pub shuffle: bool,
+    /// Percent. Some devices do not have a volume
+    pub volume: Option<u32>,
+}
+
+impl PlaybackState {
+    /// The `spotify.context.*`, `spotify.device.*`, `spotify.repeat`, `spotify.shuffle` and `spotify.volume` template
+    /// variables.
+    pub fn variables(&self) -> HashMap<String, String> {
+        let context: ContextData = self.context.to_owned().unwrap_or_default();
+        return HashMap::from([
+            (String::from("spotify.context.kind"), context.kind),
+            (String::from("spotify.context.name"), context.name),
+            (String::from("spotify.context.uri"), context.uri),
+            (String::from("spotify.context.url"), context.url),
+            (
+                String::from("spotify.device.name"),
+                self.device_name.to_owned(),
+            ),
+            (
+                String::from("spotify.device.type"),
+                self.device_type.to_owned(),
+            ),
+            (String::from("spotify.repeat"), self.repeat.to_owned()),
+            (String::from("spotify.shuffle"), self.shuffle.to_string()),
+            (
+                String::from("spotify.volume"),
+                self.volume
+                    .map(|volume: u32| volume.to_string())
+                    .unwrap_or_default(),
+            ),
+        ]);
+    }
+}
+
+/// What playback was started from, such as a playlist or an album.
+#[derive(Clone, Debug, Default)]
+pub struct ContextData {
+    /// `playlist`, `album`, `artist`, `show` or `collection` for Liked Songs
+    pub kind: String,
+    /// Only read if templates use `spotify.context.name`, as it needs another request for each new context
+    pub name: String,
+    pub uri: String,
+    pub url: String,
+}
+
+impl ContextData {
+    fn new(context: Context) -> Self {
+        return Self {
+            kind: <&str>::from(context._type).to_owned(),
+            name: String::new(),
+            url: spotify_url(&context.external_urls),
+            uri: context.uri,
+        };
+    }
+}
+
+/// A track or podcast episode.
+#[derive(Debug)]
+pub enum PlayingItem {
+    Track(TrackData),
+    Episode(EpisodeData),
+}
+
+impl PlayingItem {
+    /// The `spotify.*` template variables of this item. Only the variables of its kind are included, the others keep
+    /// their fallback values.
+    pub fn variables(&self) -> HashMap<String, String> {
+        let (kind, mut variables) = match self {
+            PlayingItem::Track(track) => ("track", track.variables()),
+            PlayingItem::Episode(episode) => ("episode", episode.variables()),
+        };
+        variables.insert(String::from("spotify.item.kind"), String::from(kind));
+        return variables;
+    }
+
+    /// Milliseconds
+    pub fn duration(&self) -> u64 {
+        return match self {
+            PlayingItem::Track(track) => track.duration,
+            PlayingItem::Episode(episode) => episode.duration,
+        };
     }
 }
 
@@ -170,8 +965,19 @@ pub async fn get_currently_playing_track(
 pub struct TrackData {
     pub album_name: String,
     pub album_cover_url: String,
+    /// Empty if the release date is unknown
+    pub album_year: String,
+    /// Name and Spotify URL of each artist, in the order of `artists`
+    pub artist_urls: Vec<(String, String)>,
     pub artists: String,
+    pub disc_number: i32,
+    /// Milliseconds
+    pub duration: u64,
+    pub explicit: bool,
     pub name: String,
+    /// 0 to 100
+    pub popularity: u32,
+    pub track_number: u32,
     pub track_url: String,
 }
 
@@ -180,9 +986,785 @@ impl TrackData {
         return Self {
             album_name: config.album_name.to_owned(),
             album_cover_url: config.album_cover_url.to_owned(),
+            album_year: String::new(),
+            artist_urls: Vec::new(),
             artists: config.artists.to_owned(),
+            disc_number: 0,
+            duration: 0,
+            explicit: false,
             name: config.name.to_owned(),
+            popularity: 0,
+            track_number: 0,
             track_url: config.track_url.to_owned(),
         };
     }
+
+    /// The `spotify.track.*` and `spotify.album.*` template variables.
+    pub fn variables(&self) -> HashMap<String, String> {
+        let mut variables: HashMap<String, String> = HashMap::from([
+            (String::from("spotify.track.name"), self.name.to_owned()),
+            (
+                String::from("spotify.track.artists"),
+                self.artists.to_owned(),
+            ),
+            (
+                String::from("spotify.track.artist_urls"),
+                self.artist_urls
+                    .iter()
+                    .map(|(_, url)| url.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", "),
+            ),
+            (String::from("spotify.track.url"), self.track_url.to_owned()),
+            (
+                String::from("spotify.track.number"),
+                self.track_number.to_string(),
+            ),
+            (
+                String::from("spotify.track.disc"),
+                self.disc_number.to_string(),
+            ),
+            (
+                String::from("spotify.track.explicit"),
+                self.explicit.to_string(),
+            ),
+            (
+                String::from("spotify.track.popularity"),
+                self.popularity.to_string(),
+            ),
+            (
+                String::from("spotify.album.cover"),
+                self.album_cover_url.to_owned(),
+            ),
+            (
+                String::from("spotify.album.name"),
+                self.album_name.to_owned(),
+            ),
+            (
+                String::from("spotify.album.year"),
+                self.album_year.to_owned(),
+            ),
+        ]);
+
+        for index in 0..INDEXED_ARTISTS {
+            let (name, url) = self.artist_urls.get(index).cloned().unwrap_or_default();
+            variables.insert(format!("spotify.track.artist.{}.name", index + 1), name);
+            variables.insert(format!("spotify.track.artist.{}.url", index + 1), url);
+        }
+
+        return variables;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EpisodeData {
+    /// Milliseconds
+    pub duration: u64,
+    pub image_url: String,
+    pub name: String,
+    pub show_name: String,
+    pub show_publisher: String,
+    pub url: String,
+}
+
+impl EpisodeData {
+    /// The `spotify.episode.*` and `spotify.show.*` template variables.
+    pub fn variables(&self) -> HashMap<String, String> {
+        return HashMap::from([
+            (String::from("spotify.episode.name"), self.name.to_owned()),
+            (
+                String::from("spotify.episode.image"),
+                self.image_url.to_owned(),
+            ),
+            (String::from("spotify.episode.url"), self.url.to_owned()),
+            (String::from("spotify.show.name"), self.show_name.to_owned()),
+            (
+                String::from("spotify.show.publisher"),
+                self.show_publisher.to_owned(),
+            ),
+        ]);
+    }
+}
+
+/// All `spotify.*` template variables as they are when Spotify is not the media source. Track variables use
+/// `spotify.fallback`, the others are empty.
+pub fn fallback_variables(config: &SpotifyFallbackConfig) -> HashMap<String, String> {
+    let mut variables: HashMap<String, String> = TrackData::fallback(config).variables();
+    variables.extend(EpisodeData::default().variables());
+    variables.extend(
+        PlaybackState::default()
+            .variables()
+            .into_keys()
+            .map(|variable: String| (variable, String::new())),
+    );
+    for variable in [
+        "spotify.item.kind",
+        "spotify.progress",
+        "spotify.progress_ms",
+        "spotify.duration",
+        "spotify.duration_ms",
+        "spotify.track.number",
+        "spotify.track.disc",
+        "spotify.track.explicit",
+        "spotify.track.popularity",
+    ] {
+        variables.insert(String::from(variable), String::new());
+    }
+    variables.insert(String::from("spotify.is_playing"), false.to_string());
+    variables.insert(
+        String::from("spotify.status"),
+        SpotifyStatus::Disabled.to_string(),
+    );
+    return variables;
+}
+
+/// `{{spotify.status}}`, the state of the requests to the Spotify Web API.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpotifyStatus {
+    /// Spotify is not set up or could not be authorized
+    #[default]
+    Disabled,
+    /// The last request succeeded
+    Ok,
+    /// Requests fail and are retried with backoff
+    Error,
+    /// Spotify asked to wait before the next request
+    RateLimited,
+    /// The refresh token was revoked or has expired. Requests start again once ddrpc is authorized again
+    Unauthorized,
+}
+
+impl std::fmt::Display for SpotifyStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(match self {
+            SpotifyStatus::Disabled => "disabled",
+            SpotifyStatus::Ok => "ok",
+            SpotifyStatus::Error => "error",
+            SpotifyStatus::RateLimited => "rate_limited",
+            SpotifyStatus::Unauthorized => "unauthorized",
+        });
+    }
+}
+
+/// Kind of a failed request, for how to retry it.
+enum RequestError {
+    /// HTTP 429, with the time from the `Retry-After` header
+    RateLimited(Option<Duration>),
+    /// The token endpoint rejected the refresh token
+    Unauthorized,
+    Other,
+}
+
+impl RequestError {
+    fn new(error: &ClientError) -> Self {
+        let ClientError::Http(error) = error else {
+            return Self::Other;
+        };
+        let HttpError::StatusCode(response) = error.as_ref() else {
+            return Self::Other;
+        };
+
+        return match response.status().as_u16() {
+            429 => Self::RateLimited(
+                response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value: &str| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs),
+            ),
+            400 | 401 if response.url().path() == "/api/token" => Self::Unauthorized,
+            _ => Self::Other,
+        };
+    }
+}
+
+/// Outcome of recent requests, used to back off and to show the last media while requests fail.
+#[derive(Default)]
+struct RequestState {
+    /// Media from the last successful request and when it was read. The media is `None` if nothing was playing
+    last: Option<(Option<MediaData>, Instant)>,
+    /// Requests that failed in a row
+    failures: u32,
+    /// No requests are made before this
+    retry_at: Option<Instant>,
+    status: SpotifyStatus,
+}
+
+impl RequestState {
+    fn succeeded(&mut self, media: Option<MediaData>) -> () {
+        if self.failures > 0 {
+            info!("Spotify requests succeed again");
+        }
+        self.last = Some((media, Instant::now()));
+        self.failures = 0;
+        self.retry_at = None;
+        self.status = SpotifyStatus::Ok;
+    }
+
+    fn failed(&mut self, error: ClientError) -> () {
+        self.failed_with(RequestError::new(&error), &error);
+    }
+
+    fn failed_with(&mut self, kind: RequestError, error: &dyn std::fmt::Display) -> () {
+        self.failures += 1;
+        let backoff: Duration = BACKOFF_MIN
+            .saturating_mul(2u32.saturating_pow(self.failures - 1))
+            .min(BACKOFF_MAX);
+
+        match kind {
+            RequestError::Unauthorized => {
+                if self.status != SpotifyStatus::Unauthorized {
+                    error!("Spotify rejected the refresh token. Run `ddrpc spotify login` to authorize ddrpc again");
+                }
+                self.status = SpotifyStatus::Unauthorized;
+                self.last = None;
+                self.retry_at = Some(Instant::now() + UNAUTHORIZED_RETRY);
+            }
+            RequestError::RateLimited(retry_after) => {
+                let wait: Duration = retry_after.unwrap_or(backoff);
+                warn!(
+                    "Spotify rate limit reached, retrying in {}s",
+                    wait.as_secs()
+                );
+                self.status = SpotifyStatus::RateLimited;
+                self.retry_at = Some(Instant::now() + wait);
+            }
+            RequestError::Other => {
+                match self.failures {
+                    1 => warn!(
+                        "Could not read Spotify playback, retrying in {}s: {error}",
+                        backoff.as_secs()
+                    ),
+                    _ => debug!(
+                        "Could not read Spotify playback, retrying in {}s: {error}",
+                        backoff.as_secs()
+                    ),
+                }
+                self.status = SpotifyStatus::Error;
+                self.retry_at = Some(Instant::now() + backoff);
+            }
+        }
+    }
+
+    /// Media from the last successful request if it is not older than `stale_for`, with its position advanced.
+    fn cached(&self, stale_for: Duration) -> Option<MediaData> {
+        let (media, read) = self.last.as_ref()?;
+        if read.elapsed() > stale_for {
+            return None;
+        }
+
+        let mut media: MediaData = media.to_owned()?;
+        if media.status == "playing" {
+            media.position += read.elapsed().as_millis() as u64;
+            if media.length > 0 {
+                media.position = media.position.min(media.length);
+            }
+            media.variables.extend([
+                (
+                    String::from("spotify.progress"),
+                    format_duration(media.position / 1000),
+                ),
+                (
+                    String::from("spotify.progress_ms"),
+                    media.position.to_string(),
+                ),
+            ]);
+        }
+        return Some(media);
+    }
+}
+
+/// The currently playing track or episode from the Spotify Web API, as the `spotify_api` entry in `media.order`. The
+/// `spotify.*` variables only have this provider's data when it is the chosen source.
+pub struct SpotifyProvider {
+    client: SpotifyClient,
+    /// Whether templates use variables from the playback state endpoint
+    playback_state: bool,
+    /// Whether templates use `spotify.context.name`
+    context_names: bool,
+    /// Context names by URI, so each context is only requested once. Holds at most [`CONTEXT_NAMES_MAX`]
+    names: std::sync::Mutex<HashMap<String, String>>,
+    /// How long the last media is shown while requests fail, from `spotify.stale_for`
+    stale_for: Duration,
+    requests: std::sync::Mutex<RequestState>,
+}
+
+impl SpotifyProvider {
+    pub fn new(client: SpotifyClient, config: &Config) -> Self {
+        let variables: HashSet<String> = template_variables(config);
+        return Self {
+            client,
+            playback_state: variables
+                .iter()
+                .any(|variable: &String| is_playback_state_variable(variable)),
+            context_names: variables.contains("spotify.context.name"),
+            names: std::sync::Mutex::new(HashMap::new()),
+            stale_for: Duration::from_secs(config.spotify.stale_for),
+            requests: std::sync::Mutex::new(RequestState::default()),
+        };
+    }
+
+    async fn request(&self) -> ClientResult<Option<MediaData>> {
+        let Some(mut playback) =
+            get_currently_playing_item(&self.client, self.playback_state).await?
+        else {
+            return Ok(None);
+        };
+
+        if self.context_names {
+            if let Some(context) = playback
+                .state
+                .as_mut()
+                .and_then(|state: &mut PlaybackState| state.context.as_mut())
+            {
+                context.name = self.context_name(context).await;
+            }
+        }
+
+        return Ok(Some(playback.media_data()));
+    }
+
+    /// Name of the playlist, album, artist or show playback was started from. Empty if it could not be read, such as
+    /// for playlists made by Spotify, which the Web API no longer serves.
+    async fn context_name(&self, context: &ContextData) -> String {
+        let cached: Option<String> = self
+            .names
+            .lock()
+            .expect("Context name mutex poisoned")
+            .get(&context.uri)
+            .cloned();
+        if let Some(name) = cached {
+            return name;
+        }
+
+        return match self.request_context_name(context).await {
+            Err(error) => {
+                debug!("Could not read the name of {}: {error}", context.uri);
+                String::new()
+            }
+            Ok(name) => {
+                trace!("Name of {} is \"{name}\"", context.uri);
+                let mut names = self.names.lock().expect("Context name mutex poisoned");
+                if names.len() >= CONTEXT_NAMES_MAX {
+                    names.clear();
+                }
+                names.insert(context.uri.to_owned(), name.to_owned());
+                name
+            }
+        };
+    }
+
+    /// Restores the refresh token from the credentials store, such as one saved by `ddrpc spotify login` while ddrpc
+    /// runs. Returns `false` if there is no refresh token other than the one Spotify rejected.
+    async fn reload_token(&self) -> bool {
+        let mut config: Config = match read_config_file(false) {
+            Err(error) => {
+                debug!("Could not read the config for a new Spotify refresh token: {error}");
+                return false;
+            }
+            Ok(config) => config,
+        };
+        if let Err(error) = credentials::load(&mut config).await {
+            debug!("Could not read the credentials for a new Spotify refresh token: {error}");
+            return false;
+        }
+
+        let rejected: Option<String> = self
+            .client
+            .get_token()
+            .lock()
+            .await
+            .expect("Token mutex poisoned")
+            .as_ref()
+            .and_then(|token: &Token| token.refresh_token.to_owned());
+        if config.spotify.refresh_token.is_empty()
+            || rejected.as_ref() == Some(&config.spotify.refresh_token)
+        {
+            trace!("No new Spotify refresh token saved");
+            return false;
+        }
+
+        info!("Found a new Spotify refresh token, requesting playback again");
+        restore_token(&config.spotify, &self.client).await;
+        return true;
+    }
+
+    async fn request_context_name(&self, context: &ContextData) -> Result<String, Box<dyn Error>> {
+        let uri: &str = &context.uri;
+        return Ok(match context.kind.parse::<Type>()? {
+            Type::Album => self.client.album(AlbumId::from_uri(uri)?, None).await?.name,
+            Type::Artist => self.client.artist(ArtistId::from_uri(uri)?).await?.name,
+            Type::Playlist => {
+                self.client
+                    .playlist(PlaylistId::from_uri(uri)?, None, None)
+                    .await?
+                    .name
+            }
+            Type::Show => {
+                self.client
+                    .get_a_show(ShowId::from_uri(uri)?, None)
+                    .await?
+                    .name
+            }
+            // The Web API has no name for the library
+            Type::Collection => String::from("Liked Songs"),
+            kind => return Err(format!("Context of type {kind} has no name").into()),
+        });
+    }
+}
+
+#[async_trait]
+impl MediaProvider for SpotifyProvider {
+    fn source(&self) -> &str {
+        return "spotify_api";
+    }
+
+    /// Requests are skipped while backing off, and the last media is reused for `spotify.stale_for` seconds. After
+    /// the refresh token is rejected, requests are only made again once a new one was saved, which is checked for every
+    /// [`UNAUTHORIZED_RETRY`].
+    async fn current(&self, _config: &Config) -> Option<MediaData> {
+        let unauthorized: bool = {
+            let requests = self
+                .requests
+                .lock()
+                .expect("Spotify request mutex poisoned");
+            if requests
+                .retry_at
+                .is_some_and(|retry_at: Instant| Instant::now() < retry_at)
+            {
+                trace!("Backing off, not requesting Spotify playback");
+                return requests.cached(self.stale_for);
+            }
+            requests.status == SpotifyStatus::Unauthorized
+        };
+        if unauthorized && !self.reload_token().await {
+            self.requests
+                .lock()
+                .expect("Spotify request mutex poisoned")
+                .retry_at = Some(Instant::now() + UNAUTHORIZED_RETRY);
+            return None;
+        }
+
+        let result: ClientResult<Option<MediaData>> = self.request().await;
+
+        let mut requests = self
+            .requests
+            .lock()
+            .expect("Spotify request mutex poisoned");
+        return match result {
+            Ok(media) => {
+                requests.succeeded(media.to_owned());
+                media
+            }
+            Err(error) => {
+                requests.failed(error);
+                requests.cached(self.stale_for)
+            }
+        };
+    }
+
+    fn variables(&self) -> HashMap<String, String> {
+        let status: SpotifyStatus = self
+            .requests
+            .lock()
+            .expect("Spotify request mutex poisoned")
+            .status;
+        return HashMap::from([(String::from("spotify.status"), status.to_string())]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode() -> EpisodeData {
+        return EpisodeData {
+            duration: 3_600_000,
+            image_url: String::from("https://i.scdn.co/image/episode"),
+            name: String::from("Episode"),
+            show_name: String::from("Show"),
+            show_publisher: String::from("Publisher"),
+            url: String::from("https://open.spotify.com/episode/1"),
+        };
+    }
+
+    fn track() -> TrackData {
+        return TrackData {
+            album_name: String::from("Album"),
+            album_cover_url: String::from("https://i.scdn.co/image/album"),
+            album_year: String::from("2001"),
+            artist_urls: vec![
+                (
+                    String::from("One"),
+                    String::from("https://open.spotify.com/artist/1"),
+                ),
+                (
+                    String::from("Two"),
+                    String::from("https://open.spotify.com/artist/2"),
+                ),
+            ],
+            artists: String::from("One, Two"),
+            disc_number: 1,
+            duration: 215_000,
+            explicit: true,
+            name: String::from("Song"),
+            popularity: 42,
+            track_number: 3,
+            track_url: String::from("https://open.spotify.com/track/1"),
+        };
+    }
+
+    #[test]
+    fn episode_variables() {
+        let variables: HashMap<String, String> = PlayingItem::Episode(episode()).variables();
+        assert_eq!(variables["spotify.item.kind"], "episode");
+        assert_eq!(variables["spotify.episode.name"], "Episode");
+        assert_eq!(
+            variables["spotify.episode.image"],
+            "https://i.scdn.co/image/episode"
+        );
+        assert_eq!(variables["spotify.show.name"], "Show");
+        assert_eq!(variables["spotify.show.publisher"], "Publisher");
+        assert!(!variables.contains_key("spotify.track.name"));
+
+        let variables: HashMap<String, String> = PlayingItem::Track(track()).variables();
+        assert_eq!(variables["spotify.item.kind"], "track");
+        assert!(!variables.contains_key("spotify.episode.name"));
+    }
+
+    #[test]
+    fn fallback_variables_cover_every_item() {
+        let fallback: HashMap<String, String> =
+            fallback_variables(&Config::default().spotify.fallback);
+        for item in [PlayingItem::Track(track()), PlayingItem::Episode(episode())] {
+            for variable in item.variables().keys() {
+                assert!(fallback.contains_key(variable), "{variable}");
+            }
+        }
+        assert_eq!(fallback["spotify.item.kind"], "");
+    }
+
+    #[test]
+    fn paused_playback_keeps_item() {
+        let playback: Playback = Playback {
+            item: PlayingItem::Track(track()),
+            is_playing: false,
+            progress: 75_500,
+            state: None,
+        };
+        let variables: HashMap<String, String> = playback.variables();
+        assert_eq!(variables["spotify.track.name"], "Song");
+        assert_eq!(variables["spotify.progress"], "1:15");
+        assert_eq!(variables["spotify.progress_ms"], "75500");
+        assert_eq!(variables["spotify.duration"], "3:35");
+        assert_eq!(variables["spotify.duration_ms"], "215000");
+        assert_eq!(variables["spotify.is_playing"], "false");
+        assert!(!variables.contains_key("spotify.device.name"));
+
+        let media: MediaData = playback.media_data();
+        assert_eq!(media.status, "paused");
+        assert_eq!(media.position, 75_500);
+        assert_eq!(media.length, 215_000);
+    }
+
+    #[test]
+    fn scopes_for_used_variables() {
+        let scopes = |variables: &[&str]| -> HashSet<String> {
+            return required_scopes(
+                &variables
+                    .iter()
+                    .map(|variable| variable.to_string())
+                    .collect(),
+            );
+        };
+        assert_eq!(
+            scopes(&["spotify.track.name", "spotify.progress"]),
+            HashSet::from([String::from(CURRENTLY_PLAYING_SCOPE)])
+        );
+        assert!(scopes(&["spotify.device.name"]).contains("user-read-playback-state"));
+        assert!(scopes(&["spotify.shuffle"]).contains("user-read-playback-state"));
+        assert!(!scopes(&["spotify.context.uri"]).contains("playlist-read-private"));
+        assert!(scopes(&["spotify.context.name"]).contains("playlist-read-private"));
+        assert!(!is_playback_state_variable("spotify.track.name"));
+    }
+
+    #[test]
+    fn extended_track_and_state_variables() {
+        let variables: HashMap<String, String> = track().variables();
+        assert_eq!(variables["spotify.album.year"], "2001");
+        assert_eq!(variables["spotify.track.number"], "3");
+        assert_eq!(variables["spotify.track.explicit"], "true");
+        assert_eq!(variables["spotify.track.artist.2.name"], "Two");
+        assert_eq!(
+            variables["spotify.track.artist.2.url"],
+            "https://open.spotify.com/artist/2"
+        );
+        assert_eq!(variables["spotify.track.artist.3.name"], "");
+
+        let state: PlaybackState = PlaybackState {
+            context: Some(ContextData {
+                kind: String::from("playlist"),
+                name: String::from("Mix"),
+                uri: String::from("spotify:playlist:1"),
+                url: String::from("https://open.spotify.com/playlist/1"),
+            }),
+            device_name: String::from("Desktop"),
+            device_type: String::from("computer"),
+            repeat: String::from("context"),
+            shuffle: true,
+            volume: None,
+        };
+        let variables: HashMap<String, String> = state.variables();
+        assert_eq!(variables["spotify.context.kind"], "playlist");
+        assert_eq!(variables["spotify.context.name"], "Mix");
+        assert_eq!(variables["spotify.device.type"], "computer");
+        assert_eq!(variables["spotify.shuffle"], "true");
+        assert_eq!(variables["spotify.volume"], "");
+        assert!(variables
+            .keys()
+            .all(|variable: &String| is_playback_state_variable(variable)));
+    }
+
+    #[test]
+    fn redirect_address_from_uri() {
+        let mut config: SpotifyConfig = Config::default().spotify;
+        config.redirect_uri = String::from("http://127.0.0.1:8888/spotify/callback");
+        assert_eq!(
+            redirect_address(&config).unwrap(),
+            (8888, String::from("/spotify/callback"))
+        );
+
+        config.redirect_uri = String::from("http://localhost");
+        assert_eq!(redirect_address(&config).unwrap(), (80, String::from("/")));
+        config.redirect_port = Some(3001);
+        assert_eq!(
+            redirect_address(&config).unwrap(),
+            (3001, String::from("/"))
+        );
+
+        config.redirect_uri = String::from("https://example.com/callback");
+        assert!(redirect_address(&config).is_err());
+    }
+
+    /// Sends `request_line` to [`handle_callback`] and returns its result and the HTTP response.
+    async fn callback(request_line: &str) -> (Result<Option<String>, String>, String) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut browser: TcpStream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        browser
+            .write_all(format!("{request_line}\r\nHost: 127.0.0.1\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let result: Result<Option<String>, String> = handle_callback(stream, "/callback")
+            .await
+            .map_err(|error| error.to_string());
+        let mut response: String = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut browser, &mut response)
+            .await
+            .unwrap();
+        return (result, response);
+    }
+
+    #[tokio::test]
+    async fn callback_requests() {
+        let (result, response) = callback("GET /callback?code=abc&state=xyz HTTP/1.1").await;
+        assert_eq!(
+            result,
+            Ok(Some(String::from(
+                "http://127.0.0.1/callback?code=abc&state=xyz"
+            )))
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        let (result, response) = callback("GET /favicon.ico HTTP/1.1").await;
+        assert_eq!(result, Ok(None));
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+        let (result, _) = callback("GET /callback?error=access_denied HTTP/1.1").await;
+        assert!(result.unwrap_err().contains("access_denied"));
+    }
+
+    #[test]
+    fn granted_scopes_of_saved_token() {
+        let mut config: SpotifyConfig = Config::default().spotify;
+        // Tokens saved before scopes were recorded
+        assert_eq!(
+            granted_scopes(&config),
+            HashSet::from([String::from(CURRENTLY_PLAYING_SCOPE)])
+        );
+        config.scopes = vec![
+            String::from(CURRENTLY_PLAYING_SCOPE),
+            String::from("user-read-playback-state"),
+        ];
+        assert_eq!(granted_scopes(&config).len(), 2);
+    }
+
+    fn io_error() -> ClientError {
+        return ClientError::Io(std::io::Error::from(std::io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn failed_requests_back_off() {
+        let mut requests: RequestState = RequestState::default();
+        let mut waits: Vec<u64> = Vec::new();
+        for _ in 0..8 {
+            requests.failed(io_error());
+            let wait: Duration = requests.retry_at.unwrap() - Instant::now();
+            // Rounded up, as a little time passed since the failure
+            waits.push(wait.as_secs() + 1);
+        }
+        assert_eq!(waits, [5, 10, 20, 40, 80, 160, 300, 300]);
+        assert_eq!(requests.status, SpotifyStatus::Error);
+
+        requests.succeeded(None);
+        assert_eq!(requests.failures, 0);
+        assert_eq!(requests.retry_at, None);
+        assert_eq!(requests.status, SpotifyStatus::Ok);
+    }
+
+    #[test]
+    fn rejected_refresh_token_is_retried_later() {
+        let mut requests: RequestState = RequestState::default();
+        requests.succeeded(None);
+        requests.failed_with(RequestError::Unauthorized, &"invalid_grant");
+
+        assert_eq!(requests.status, SpotifyStatus::Unauthorized);
+        assert!(requests.last.is_none());
+        let wait: Duration = requests.retry_at.unwrap() - Instant::now();
+        assert_eq!(wait.as_secs() + 1, UNAUTHORIZED_RETRY.as_secs());
+
+        requests.succeeded(None);
+        assert_eq!(requests.status, SpotifyStatus::Ok);
+    }
+
+    #[test]
+    fn cached_media_while_failing() {
+        let mut requests: RequestState = RequestState::default();
+        assert_eq!(requests.cached(Duration::from_secs(60)), None);
+
+        requests.succeeded(Some(MediaData {
+            status: String::from("playing"),
+            position: 10_000,
+            length: 215_000,
+            ..Default::default()
+        }));
+        // Pretend the request was two seconds ago
+        if let Some((_, read)) = requests.last.as_mut() {
+            *read -= Duration::from_secs(2);
+        }
+        requests.failed(io_error());
+
+        let media: MediaData = requests.cached(Duration::from_secs(60)).unwrap();
+        assert!(
+            (12_000..13_000).contains(&media.position),
+            "{}",
+            media.position
+        );
+        assert_eq!(media.variables["spotify.progress"], "0:12");
+        assert_eq!(requests.cached(Duration::from_secs(1)), None);
+    }
 }
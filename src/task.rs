@@ -0,0 +1,223 @@
+use crate::{
+    credentials::PASSPHRASE_VARIABLE,
+    daemon::{send_message, DaemonMessage},
+    parser::CliRun,
+    prelude::*,
+};
+use std::{
+    io::{self, ErrorKind},
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    process::ExitStatus,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::process::{Child, Command};
+
+/// A command started with `ddrpc run`, as reported to the presence loop.
+#[derive(Clone, Debug)]
+pub struct TaskState {
+    /// PID of the `ddrpc run` process
+    pub id: u32,
+    pub name: String,
+    /// Details text given with `--details`. Replaces `task.details` from the config
+    pub details: Option<String>,
+    pub command: String,
+    /// Unix timestamp in seconds
+    pub started: u64,
+    pub result: Option<TaskResult>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskResult {
+    pub exit_code: i32,
+    /// Unix timestamp in seconds
+    pub finished: u64,
+}
+
+impl TaskState {
+    /// Seconds the task has been running, or ran for if it has finished.
+    pub fn elapsed(&self) -> u64 {
+        let end: u64 = self
+            .result
+            .as_ref()
+            .map_or_else(unix_time, |result: &TaskResult| result.finished);
+        return end.saturating_sub(self.started);
+    }
+
+    /// `running`, `succeeded` or `failed`.
+    pub fn status(&self) -> &'static str {
+        return match &self.result {
+            None => "running",
+            Some(result) if result.exit_code == 0 => "succeeded",
+            Some(_) => "failed",
+        };
+    }
+}
+
+pub fn unix_time() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+}
+
+/// Applies a message from `ddrpc run` to the list of known tasks.
+pub fn handle_message(tasks: &mut Vec<TaskState>, message: DaemonMessage) -> () {
+    match message {
+        DaemonMessage::TaskStarted {
+            id,
+            name,
+            details,
+            command,
+            started,
+        } => {
+            info!("Task \"{name}\" started");
+            tasks.retain(|task: &TaskState| task.id != id);
+            tasks.push(TaskState {
+                id,
+                name,
+                details,
+                command,
+                started,
+                result: None,
+            });
+        }
+        DaemonMessage::TaskFinished {
+            id,
+            exit_code,
+            finished,
+        } => match tasks.iter_mut().find(|task: &&mut TaskState| task.id == id) {
+            None => debug!("Finished task {id} is not known"),
+            Some(task) => {
+                info!("Task \"{}\" finished with exit code {exit_code}", task.name);
+                task.result = Some(TaskResult {
+                    exit_code,
+                    finished,
+                });
+            }
+        },
+    }
+}
+
+/// Task to show in the activity: the most recently started running task, otherwise the most recently finished one if it
+/// finished less than `TaskConfig::finished_for` seconds ago. Expired tasks and tasks whose `ddrpc run` process died
+/// without reporting are removed.
+pub fn current_task<'t>(
+    config: &TaskConfig,
+    tasks: &'t mut Vec<TaskState>,
+) -> Option<&'t TaskState> {
+    let now: u64 = unix_time();
+    tasks.retain(|task: &TaskState| match &task.result {
+        None => Path::new(&format!("/proc/{}", task.id)).exists(),
+        Some(result) => now < result.finished + config.finished_for,
+    });
+
+    return tasks
+        .iter()
+        .filter(|task: &&TaskState| task.result.is_none())
+        .max_by_key(|task: &&TaskState| task.started)
+        .or_else(|| {
+            tasks
+                .iter()
+                .max_by_key(|task: &&TaskState| task.result.as_ref().map(|result| result.finished))
+        });
+}
+
+/// Runs a command and reports it to the presence loop while it runs. Returns the command's exit code, or 128 plus the
+/// signal number if it was killed by a signal, like a shell does. A command that cannot be started returns 127 if it
+/// was not found and 126 otherwise, also like a shell. The command still runs if no presence loop is running.
+#[instrument(skip_all)]
+pub async fn run_task(args: CliRun) -> Result<i32, Box<dyn Error>> {
+    let Some((program, program_args)) = args.command.split_first() else {
+        return Err("No command given".into());
+    };
+
+    let name: String = args.name.unwrap_or_else(|| {
+        Path::new(program)
+            .file_name()
+            .map_or(program.to_owned(), |name| {
+                name.to_string_lossy().into_owned()
+            })
+    });
+    let id: u32 = std::process::id();
+
+    // The command has no use for the passphrase of the credentials file
+    let spawned: io::Result<Child> = Command::new(program)
+        .args(program_args)
+        .env_remove(PASSPHRASE_VARIABLE)
+        .spawn();
+    let mut child: Child = match spawned {
+        Err(error) => {
+            eprintln!("ddrpc: {program}: {error}");
+            return Ok(match error.kind() {
+                ErrorKind::NotFound => 127,
+                _ => 126,
+            });
+        }
+        Ok(child) => child,
+    };
+
+    if let Err(error) = send_message(&DaemonMessage::TaskStarted {
+        id,
+        name,
+        details: args.details,
+        command: args.command.join(" "),
+        started: unix_time(),
+    }) {
+        debug!("Presence loop is not running: {error}");
+    }
+
+    // Ctrl+C reaches the command too, so wait for it to exit instead of exiting before it does
+    let status: ExitStatus = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            _ = tokio::signal::ctrl_c() => trace!("Interrupted, waiting for the command to exit"),
+        }
+    };
+
+    let exit_code: i32 = status
+        .code()
+        .or_else(|| status.signal().map(|signal: i32| 128 + signal))
+        .unwrap_or(1);
+
+    if let Err(error) = send_message(&DaemonMessage::TaskFinished {
+        id,
+        exit_code,
+        finished: unix_time(),
+    }) {
+        debug!("Presence loop is not running: {error}");
+    }
+
+    return Ok(exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &[&str]) -> CliRun {
+        return CliRun {
+            details: None,
+            name: None,
+            command: command.iter().map(|arg: &&str| arg.to_string()).collect(),
+        };
+    }
+
+    #[tokio::test]
+    async fn exit_codes_pass_through() {
+        assert_eq!(run_task(run(&["sh", "-c", "exit 7"])).await.unwrap(), 7);
+        assert_eq!(
+            run_task(run(&["sh", "-c", "kill -TERM $$"])).await.unwrap(),
+            128 + libc::SIGTERM
+        );
+
+        // Commands that cannot be started exit like they would in a shell
+        assert_eq!(
+            run_task(run(&["ddrpc-test-no-such-command"]))
+                .await
+                .unwrap(),
+            127
+        );
+        let directory: String = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(run_task(run(&[&directory])).await.unwrap(), 126);
+    }
+}